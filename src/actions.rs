@@ -0,0 +1,285 @@
+use std::path::PathBuf;
+
+use bevy::{
+    input::{
+        gamepad::{GamepadEvent, GamepadEventType},
+        InputSystem,
+    },
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use serde::Deserialize;
+
+pub struct ActionPlugin;
+
+impl Plugin for ActionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Bindings::load())
+            .init_resource::<Actions>()
+            .init_resource::<ConnectedGamepads>()
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                track_gamepads.label(ActionSystems::TrackGamepads),
+            )
+            .add_system_to_stage(
+                CoreStage::PreUpdate,
+                update_actions
+                    .after(InputSystem)
+                    .after(ActionSystems::TrackGamepads),
+            );
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, SystemLabel, Clone)]
+pub enum ActionSystems {
+    TrackGamepads,
+}
+
+/// How far a stick has to be pushed before it counts, the rest of its range is scaled to `0.`-`1.`
+const STICK_DEADZONE: f32 = 0.2;
+
+/// How far a stick has to be pushed up or down to climb
+const STICK_VERTICAL_THRESHOLD: f32 = 0.5;
+
+/// Something the player wants to do, independent of the key that does it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Jump,
+    Dash,
+    Pause,
+}
+
+/// The keys and gamepad buttons bound to each action
+///
+/// Keys are read from `bindings.ron` in the user's config directory, actions missing from it keep
+/// their default keys.
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    keys: HashMap<Action, Vec<KeyCode>>,
+    buttons: HashMap<Action, Vec<GamepadButtonType>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let keys = [
+            (Action::MoveLeft, vec![KeyCode::A, KeyCode::Left]),
+            (Action::MoveRight, vec![KeyCode::D, KeyCode::Right]),
+            (Action::MoveUp, vec![KeyCode::W, KeyCode::Up]),
+            (Action::MoveDown, vec![KeyCode::S, KeyCode::Down]),
+            (Action::Jump, vec![KeyCode::Space]),
+            (Action::Dash, vec![KeyCode::LShift, KeyCode::K]),
+            (Action::Pause, vec![KeyCode::Escape]),
+        ];
+
+        let buttons = [
+            (Action::MoveLeft, vec![GamepadButtonType::DPadLeft]),
+            (Action::MoveRight, vec![GamepadButtonType::DPadRight]),
+            (Action::MoveUp, vec![GamepadButtonType::DPadUp]),
+            (Action::MoveDown, vec![GamepadButtonType::DPadDown]),
+            (Action::Jump, vec![GamepadButtonType::South]),
+            (Action::Dash, vec![GamepadButtonType::West]),
+            (Action::Pause, vec![GamepadButtonType::Start]),
+        ];
+
+        Self {
+            keys: keys.iter().cloned().collect(),
+            buttons: buttons.iter().cloned().collect(),
+        }
+    }
+}
+
+impl Bindings {
+    /// Where the user's bindings are stored, `None` if there is no config directory
+    pub fn path() -> Option<PathBuf> {
+        let config_dir = if cfg!(target_os = "windows") {
+            std::env::var_os("APPDATA").map(PathBuf::from)
+        } else if cfg!(target_os = "macos") {
+            std::env::var_os("HOME").map(|home| {
+                PathBuf::from(home)
+                    .join("Library")
+                    .join("Application Support")
+            })
+        } else {
+            std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .or_else(|| {
+                    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config"))
+                })
+        };
+
+        config_dir.map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("bindings.ron"))
+    }
+
+    /// The default bindings, overridden by the ones in the user's bindings file
+    pub fn load() -> Self {
+        let mut bindings = Bindings::default();
+
+        let path = if let Some(path) = Bindings::path() {
+            path
+        } else {
+            warn!("No config directory found, using the default key bindings");
+            return bindings;
+        };
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => {
+                info!("No key bindings at {:?}, using the defaults", path);
+                return bindings;
+            }
+        };
+
+        match ron::de::from_str::<std::collections::HashMap<Action, Vec<KeyCode>>>(&contents) {
+            Ok(keys) => {
+                info!("Loaded key bindings from {:?}", path);
+                bindings.keys.extend(keys);
+            }
+            Err(error) => error!("Could not read key bindings from {:?}: {}", path, error),
+        }
+
+        bindings
+    }
+}
+
+/// The gamepads that are plugged in right now
+#[derive(Debug, Default)]
+pub struct ConnectedGamepads(pub Vec<Gamepad>);
+
+fn track_gamepads(
+    mut gamepad_events: EventReader<GamepadEvent>,
+    mut connected_gamepads: ResMut<ConnectedGamepads>,
+) {
+    for GamepadEvent(gamepad, event_type) in gamepad_events.iter() {
+        match event_type {
+            GamepadEventType::Connected => {
+                info!("Gamepad {:?} connected", gamepad);
+
+                if !connected_gamepads.0.contains(gamepad) {
+                    connected_gamepads.0.push(*gamepad);
+                }
+            }
+            GamepadEventType::Disconnected => {
+                info!("Gamepad {:?} disconnected", gamepad);
+
+                connected_gamepads
+                    .0
+                    .retain(|connected| connected != gamepad);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// The actions the player is performing this frame
+#[derive(Debug, Default)]
+pub struct Actions {
+    pressed: HashSet<Action>,
+    just_pressed: HashSet<Action>,
+    horizontal: f32,
+}
+
+impl Actions {
+    /// How far the player pushes left (`-1.`) or right (`1.`), only a stick gives values in
+    /// between
+    pub fn horizontal(&self) -> f32 {
+        self.horizontal
+    }
+
+    pub fn pressed(&self, action: Action) -> bool {
+        self.pressed.contains(&action)
+    }
+
+    pub fn just_pressed(&self, action: Action) -> bool {
+        self.just_pressed.contains(&action)
+    }
+}
+
+/// Scales a stick axis so the deadzone reads as `0.` and a full push as `1.`
+fn apply_deadzone(value: f32) -> f32 {
+    if value.abs() <= STICK_DEADZONE {
+        0.
+    } else {
+        value.signum() * (value.abs() - STICK_DEADZONE) / (1. - STICK_DEADZONE)
+    }
+}
+
+fn update_actions(
+    keyboard: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    connected_gamepads: Res<ConnectedGamepads>,
+    bindings: Res<Bindings>,
+    mut actions: ResMut<Actions>,
+) {
+    actions.pressed.clear();
+    actions.just_pressed.clear();
+
+    for (action, keys) in bindings.keys.iter() {
+        if keys.iter().any(|key| keyboard.pressed(*key)) {
+            actions.pressed.insert(*action);
+        }
+
+        if keys.iter().any(|key| keyboard.just_pressed(*key)) {
+            actions.just_pressed.insert(*action);
+        }
+    }
+
+    let mut stick = Vec2::ZERO;
+
+    for gamepad in connected_gamepads.0.iter() {
+        for (action, buttons) in bindings.buttons.iter() {
+            let button = |button_type: &GamepadButtonType| GamepadButton(*gamepad, *button_type);
+
+            if buttons
+                .iter()
+                .any(|button_type| gamepad_buttons.pressed(button(button_type)))
+            {
+                actions.pressed.insert(*action);
+            }
+
+            if buttons
+                .iter()
+                .any(|button_type| gamepad_buttons.just_pressed(button(button_type)))
+            {
+                actions.just_pressed.insert(*action);
+            }
+        }
+
+        let axis = |axis_type| {
+            gamepad_axes
+                .get(GamepadAxis(*gamepad, axis_type))
+                .map_or(0., apply_deadzone)
+        };
+
+        // The gamepad pushed the furthest wins
+        let gamepad_stick = Vec2::new(
+            axis(GamepadAxisType::LeftStickX),
+            axis(GamepadAxisType::LeftStickY),
+        );
+
+        if gamepad_stick.length() > stick.length() {
+            stick = gamepad_stick;
+        }
+    }
+
+    if stick.y >= STICK_VERTICAL_THRESHOLD {
+        actions.pressed.insert(Action::MoveUp);
+    } else if stick.y <= -STICK_VERTICAL_THRESHOLD {
+        actions.pressed.insert(Action::MoveDown);
+    }
+
+    let digital = match (
+        actions.pressed(Action::MoveLeft),
+        actions.pressed(Action::MoveRight),
+    ) {
+        (true, false) => -1.,
+        (false, true) => 1.,
+        _ => 0.,
+    };
+
+    actions.horizontal = if stick.x != 0. { stick.x } else { digital };
+}
@@ -0,0 +1,413 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    ldtk,
+    ldtk_map::LdtkMap,
+    map::{CurrentMap, LevelRect},
+    movement::Aabb,
+    MainLdtk,
+};
+
+pub struct CollisionPlugin;
+
+impl Plugin for CollisionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CollisionTable>()
+            .init_resource::<CollisionWorld>()
+            .add_system(build_collision_world);
+    }
+}
+
+/// How entities interact with a tile
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CollisionKind {
+    /// Nothing happens, entities pass right through
+    Passable,
+    /// Blocks from every side
+    Solid,
+    /// Can be jumped through from below and stood upon from above
+    OneWay,
+    /// Entities pass through, but are submerged while inside
+    Liquid,
+    /// Entities pass through, and can climb along it without falling
+    Climbable,
+    /// Entities stand on the slope's surface below their center
+    Slope(Slope),
+    /// Entities pass through, but get thrown back out of it
+    Hazard,
+}
+
+/// The shape of a slope tile, named by the direction it rises towards when walking right
+///
+/// The gentle (22.5°) slopes span two tiles, a low and a high one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Slope {
+    Up,
+    Down,
+    UpGentleLow,
+    UpGentleHigh,
+    DownGentleHigh,
+    DownGentleLow,
+}
+
+impl Slope {
+    /// Height of the surface at its left and right edge, relative to the tile
+    fn edges(&self) -> (f32, f32) {
+        match self {
+            Slope::Up => (0., 1.),
+            Slope::Down => (1., 0.),
+            Slope::UpGentleLow => (0., 0.5),
+            Slope::UpGentleHigh => (0.5, 1.),
+            Slope::DownGentleHigh => (1., 0.5),
+            Slope::DownGentleLow => (0.5, 0.),
+        }
+    }
+
+    /// Height of the surface at `x` within the tile, both relative to the tile
+    pub fn height_at(&self, x: f32) -> f32 {
+        let (left, right) = self.edges();
+
+        left + (right - left) * x.max(0.).min(1.)
+    }
+}
+
+impl Default for CollisionKind {
+    fn default() -> Self {
+        CollisionKind::Passable
+    }
+}
+
+/// The IntGrid layers of the map that take part in collision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CollisionLayer {
+    Foreground,
+    Background,
+}
+
+/// Maps the IntGrid values of each layer to their [`CollisionKind`] and grip
+///
+/// Values that are not in the table are passable, and have a grip of `1.`.
+#[derive(Debug, Clone)]
+pub struct CollisionTable {
+    kinds: HashMap<(CollisionLayer, u32), CollisionKind>,
+    grips: HashMap<(CollisionLayer, u32), f32>,
+}
+
+impl Default for CollisionTable {
+    fn default() -> Self {
+        let mut table = CollisionTable {
+            kinds: HashMap::default(),
+            grips: HashMap::default(),
+        };
+
+        // Grass
+        table.set(CollisionLayer::Foreground, 1, CollisionKind::Solid);
+        // Tree
+        table.set(CollisionLayer::Foreground, 2, CollisionKind::Climbable);
+        // Leaves
+        table.set(CollisionLayer::Foreground, 3, CollisionKind::OneWay);
+        // Slopes
+        let slopes = [
+            (4, Slope::Up),
+            (5, Slope::Down),
+            (6, Slope::UpGentleLow),
+            (7, Slope::UpGentleHigh),
+            (8, Slope::DownGentleHigh),
+            (9, Slope::DownGentleLow),
+        ];
+        for (value, slope) in slopes.iter() {
+            table.set(
+                CollisionLayer::Foreground,
+                *value,
+                CollisionKind::Slope(*slope),
+            );
+        }
+        // Ice and mud, solid ground that takes away less or more speed than grass
+        table.set(CollisionLayer::Foreground, 10, CollisionKind::Solid);
+        table.set_grip(CollisionLayer::Foreground, 10, 0.1);
+        table.set(CollisionLayer::Foreground, 11, CollisionKind::Solid);
+        table.set_grip(CollisionLayer::Foreground, 11, 2.5);
+        // Spikes
+        table.set(CollisionLayer::Foreground, 12, CollisionKind::Hazard);
+        // Water
+        table.set(CollisionLayer::Background, 3, CollisionKind::Liquid);
+
+        table
+    }
+}
+
+impl CollisionTable {
+    pub fn set(&mut self, layer: CollisionLayer, value: u32, kind: CollisionKind) {
+        self.kinds.insert((layer, value), kind);
+    }
+
+    pub fn kind(&self, layer: CollisionLayer, value: u32) -> CollisionKind {
+        self.kinds.get(&(layer, value)).copied().unwrap_or_default()
+    }
+
+    /// Sets how strongly a surface slows down entities standing on it
+    ///
+    /// Ice would be close to `0.`, sticky mud larger than `1.`.
+    pub fn set_grip(&mut self, layer: CollisionLayer, value: u32, grip: f32) {
+        self.grips.insert((layer, value), grip);
+    }
+
+    pub fn grip(&self, layer: CollisionLayer, value: u32) -> f32 {
+        self.grips.get(&(layer, value)).copied().unwrap_or(1.)
+    }
+
+    /// The kind of a cell given the values of both layers, the foreground wins unless it is passable
+    pub fn combined_kind(&self, foreground: u32, background: u32) -> CollisionKind {
+        match self.kind(CollisionLayer::Foreground, foreground) {
+            CollisionKind::Passable => self.kind(CollisionLayer::Background, background),
+            kind => kind,
+        }
+    }
+
+    /// The grip of a cell given the values of both layers, taken from the layer that decides its kind
+    pub fn combined_grip(&self, foreground: u32, background: u32) -> f32 {
+        match self.kind(CollisionLayer::Foreground, foreground) {
+            CollisionKind::Passable => self.grip(CollisionLayer::Background, background),
+            _ => self.grip(CollisionLayer::Foreground, foreground),
+        }
+    }
+}
+
+/// Something entities collide with, addressed in world cells
+pub trait CollisionGrid {
+    fn kind_at(&self, cell: IVec2) -> CollisionKind;
+
+    /// How strongly the tile slows down entities standing on it, see [`CollisionTable::set_grip`]
+    fn grip_at(&self, _cell: IVec2) -> f32 {
+        1.
+    }
+
+    /// Boxes that block like solid tiles, but are not aligned to the grid
+    fn solids(&self) -> &[Aabb] {
+        &[]
+    }
+}
+
+/// Adds the colliders of solid entities to a grid
+#[derive(Debug)]
+pub struct WithSolids<'a, G> {
+    pub grid: &'a G,
+    pub solids: &'a [Aabb],
+}
+
+impl<'a, G: CollisionGrid> CollisionGrid for WithSolids<'a, G> {
+    fn kind_at(&self, cell: IVec2) -> CollisionKind {
+        self.grid.kind_at(cell)
+    }
+
+    fn grip_at(&self, cell: IVec2) -> f32 {
+        self.grid.grip_at(cell)
+    }
+
+    fn solids(&self) -> &[Aabb] {
+        self.solids
+    }
+}
+
+/// A grid turned upside down, so entities whose gravity pulls upwards can be moved like any other
+///
+/// Neither slopes nor one way platforms work upside down, slopes turn into solid tiles and one way
+/// platforms into passable ones.
+pub struct Mirrored<'a, G> {
+    grid: &'a G,
+    solids: Vec<Aabb>,
+}
+
+impl<'a, G: CollisionGrid> Mirrored<'a, G> {
+    pub fn new(grid: &'a G) -> Self {
+        let solids = grid
+            .solids()
+            .iter()
+            .map(|solid| Aabb {
+                min: Vec2::new(solid.min.x, -solid.max.y),
+                max: Vec2::new(solid.max.x, -solid.min.y),
+            })
+            .collect();
+
+        Self { grid, solids }
+    }
+
+    fn mirror(cell: IVec2) -> IVec2 {
+        IVec2::new(cell.x, -cell.y - 1)
+    }
+}
+
+impl<'a, G: CollisionGrid> CollisionGrid for Mirrored<'a, G> {
+    fn kind_at(&self, cell: IVec2) -> CollisionKind {
+        match self.grid.kind_at(Self::mirror(cell)) {
+            CollisionKind::Slope(_) => CollisionKind::Solid,
+            CollisionKind::OneWay => CollisionKind::Passable,
+            kind => kind,
+        }
+    }
+
+    fn grip_at(&self, cell: IVec2) -> f32 {
+        self.grid.grip_at(Self::mirror(cell))
+    }
+
+    fn solids(&self) -> &[Aabb] {
+        &self.solids
+    }
+}
+
+impl<F: Fn(IVec2) -> CollisionKind> CollisionGrid for F {
+    fn kind_at(&self, cell: IVec2) -> CollisionKind {
+        self(cell)
+    }
+}
+
+/// The IntGrid value at `pos`, relative to the layer's origin
+///
+/// Returns `None` outside of the layer or if it is not an IntGrid layer.
+pub fn int_grid_value(
+    layer: &bevy_spicy_ldtk::Layer<ldtk::ProjectEntities>,
+    pos: IVec2,
+) -> Option<u32> {
+    let values = match &layer.special {
+        bevy_spicy_ldtk::SpecialValues::IntGrid { values, .. } => values,
+        _ => return None,
+    };
+
+    if pos.x >= layer.dimensions_cell.x as i32
+        || pos.x < 0
+        || pos.y >= layer.dimensions_cell.y as i32
+        || pos.y < 0
+    {
+        return None;
+    }
+
+    let idx = pos.y * layer.dimensions_cell.x as i32 + pos.x;
+
+    values.get(idx as usize).map(|value| *value as u32)
+}
+
+/// The collision kinds of every level in the world, in a single grid
+///
+/// Cells outside of every level are solid, so nothing can fall out of the world.
+#[derive(Debug, Default)]
+pub struct CollisionWorld {
+    origin: IVec2,
+    size: IVec2,
+    kinds: Vec<CollisionKind>,
+    grips: Vec<f32>,
+}
+
+impl CollisionWorld {
+    pub fn build(ldtk: &ldtk::Project, table: &CollisionTable) -> Self {
+        let rects: Vec<_> = ldtk
+            .levels
+            .iter()
+            .map(|level| LevelRect::new(level.world_position_px, level.dimensions_px))
+            .collect();
+
+        let (origin, end) = match rects.first() {
+            Some(first) => rects
+                .iter()
+                .fold((first.min, first.max), |(min, max), rect| {
+                    (min.min(rect.min), max.max(rect.max))
+                }),
+            None => return Self::default(),
+        };
+
+        let size = end - origin;
+        let mut kinds = vec![CollisionKind::Solid; (size.x * size.y) as usize];
+        let mut grips = vec![1.; (size.x * size.y) as usize];
+
+        for (level, rect) in ldtk.levels.iter().zip(rects) {
+            for y in rect.min.y..rect.max.y {
+                for x in rect.min.x..rect.max.x {
+                    let cell = IVec2::new(x, y);
+                    let pos = cell - rect.min;
+
+                    let (kind, grip) = match (
+                        int_grid_value(&level.layers.foreground, pos),
+                        int_grid_value(&level.layers.background, pos),
+                    ) {
+                        (Some(foreground), Some(background)) => (
+                            table.combined_kind(foreground, background),
+                            table.combined_grip(foreground, background),
+                        ),
+                        _ => (CollisionKind::Solid, 1.),
+                    };
+
+                    let pos = cell - origin;
+                    let idx = (pos.y * size.x + pos.x) as usize;
+                    kinds[idx] = kind;
+                    grips[idx] = grip;
+                }
+            }
+        }
+
+        Self {
+            origin,
+            size,
+            kinds,
+            grips,
+        }
+    }
+
+    /// A world of `size` cells from `origin` on, with the kinds given by `kind_at`
+    #[cfg(test)]
+    pub fn from_fn(origin: IVec2, size: IVec2, kind_at: impl Fn(IVec2) -> CollisionKind) -> Self {
+        let kinds = (0..size.y)
+            .flat_map(|y| (0..size.x).map(move |x| IVec2::new(x, y)))
+            .map(|pos| kind_at(origin + pos))
+            .collect();
+
+        Self {
+            origin,
+            size,
+            kinds,
+            grips: vec![1.; (size.x * size.y) as usize],
+        }
+    }
+
+    fn index(&self, cell: IVec2) -> Option<usize> {
+        let pos = cell - self.origin;
+
+        if pos.x >= self.size.x || pos.x < 0 || pos.y >= self.size.y || pos.y < 0 {
+            return None;
+        }
+
+        Some((pos.y * self.size.x + pos.x) as usize)
+    }
+}
+
+impl CollisionGrid for CollisionWorld {
+    fn kind_at(&self, cell: IVec2) -> CollisionKind {
+        self.index(cell)
+            .map_or(CollisionKind::Solid, |idx| self.kinds[idx])
+    }
+
+    fn grip_at(&self, cell: IVec2) -> f32 {
+        self.index(cell).map_or(1., |idx| self.grips[idx])
+    }
+}
+
+fn build_collision_world(
+    mut ldtk_map_asset_events: EventReader<AssetEvent<MainLdtk>>,
+    ldtk_assets: Res<Assets<MainLdtk>>,
+    current_map: Res<CurrentMap>,
+    collision_table: Res<CollisionTable>,
+    mut collision_world: ResMut<CollisionWorld>,
+) {
+    let map_modified = ldtk_map_asset_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            handle == &current_map.0
+        }
+        AssetEvent::Removed { handle: _ } => false,
+    });
+
+    if !map_modified && !collision_table.is_changed() {
+        return;
+    }
+
+    if let Some(LdtkMap { ldtk }) = ldtk_assets.get(&current_map.0) {
+        *collision_world = CollisionWorld::build(ldtk, &collision_table);
+    }
+}
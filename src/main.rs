@@ -1,11 +1,16 @@
+mod actions;
 mod camera;
+mod collision;
 mod ldtk_map;
 mod map;
 mod markers;
 mod movement;
 mod objects;
+mod platforms;
 mod player;
+mod tuning;
 mod ui;
+mod zones;
 
 use std::{path::PathBuf, time::Duration};
 
@@ -28,12 +33,17 @@ fn main() {
         .add_plugin(bevy_simple_tilemap::prelude::SimpleTileMapPlugin)
         .add_plugin(ldtk_map::LdtkPlugin::<ldtk::Project>::default())
         .add_plugin(benimator::AnimationPlugin)
+        .add_plugin(actions::ActionPlugin)
         .add_plugin(markers::MarkerPlugin)
+        .add_plugin(tuning::TuningPlugin)
+        .add_plugin(collision::CollisionPlugin)
         .add_plugin(map::MapPlugin)
-        .add_plugin(movement::MovementPlugin)
+        .add_plugin(movement::MovementPlugin::default())
         .add_plugin(camera::CameraPlugin)
         .add_plugin(player::PlayerPlugin)
         .add_plugin(objects::ObjectPlugin)
+        .add_plugin(platforms::PlatformPlugin)
+        .add_plugin(zones::ZonePlugin)
         .add_plugin(ui::UiPlugin)
         .add_startup_system(spawn_camera)
         .add_startup_system_to_stage(StartupStage::PreStartup, setup_game_assets)
@@ -56,12 +66,18 @@ pub struct GameAssets {
 
     ldtk_map_handle: Handle<MainLdtk>,
 
+    physics_tuning_handle: Handle<tuning::PhysicsTuning>,
+
     coin_texture_handle: Handle<Texture>,
     coin_animation_handle: Handle<SpriteSheetAnimation>,
 
     coin_pickup_handle: Handle<TextureAtlas>,
     coin_pickup_animation: Handle<SpriteSheetAnimation>,
 
+    double_jump_pickup_material: Handle<ColorMaterial>,
+    dash_pickup_material: Handle<ColorMaterial>,
+    platform_material: Handle<ColorMaterial>,
+
     text_font_handle: Handle<Font>,
 }
 
@@ -70,12 +86,15 @@ fn setup_game_assets(
     asset_server: Res<AssetServer>,
     mut texture_atlas_assets: ResMut<Assets<TextureAtlas>>,
     mut animation_assets: ResMut<Assets<SpriteSheetAnimation>>,
+    mut material_assets: ResMut<Assets<ColorMaterial>>,
 ) {
     asset_server.watch_for_changes().unwrap();
 
     let path = PathBuf::from(ldtk::FILEPATH);
     let ldtk_map_handle: Handle<MainLdtk> = asset_server.load(path.strip_prefix("assets").unwrap());
 
+    let physics_tuning_handle = asset_server.load("tuning/physics.ron");
+
     let texture_handle = asset_server.load("art/tiles.png");
     let texture_atlas = TextureAtlas::from_grid_with_padding(
         texture_handle,
@@ -108,13 +127,23 @@ fn setup_game_assets(
         Duration::from_millis(750),
     ));
 
+    let double_jump_pickup_material =
+        material_assets.add(ColorMaterial::color(Color::rgb_u8(65, 166, 246)));
+    let dash_pickup_material =
+        material_assets.add(ColorMaterial::color(Color::rgb_u8(239, 125, 87)));
+    let platform_material = material_assets.add(ColorMaterial::color(Color::rgb_u8(102, 57, 49)));
+
     commands.insert_resource(GameAssets {
         texture_atlas_handle,
         ldtk_map_handle,
+        physics_tuning_handle,
         coin_texture_handle,
         coin_animation_handle,
         coin_pickup_handle,
         coin_pickup_animation,
+        double_jump_pickup_material,
+        dash_pickup_material,
+        platform_material,
         text_font_handle,
     })
 }
@@ -1,81 +1,244 @@
 use bevy::prelude::*;
 use bevy_simple_tilemap::{prelude::TileMapBundle, Tile, TileMap};
 
-use crate::{ldtk, ldtk_map::LdtkMap, movement::LevelBounds, GameAssets, MainLdtk, GRID_SIZE};
+use crate::{
+    ldtk,
+    ldtk_map::LdtkMap,
+    movement::{LevelBounds, Position},
+    player::Player,
+    GameAssets, MainLdtk, GRID_SIZE,
+};
 
 #[derive(Debug, Default)]
 pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system(load_map).add_system(update_map);
+        app.add_event::<LevelEvent>()
+            .add_startup_system(load_map)
+            .add_system(follow_player_level)
+            .add_system(stream_levels.label(MapSystems::StreamLevels))
+            .add_system(despawn_level_members.after(MapSystems::StreamLevels));
     }
 }
 
+#[derive(Debug, Hash, PartialEq, Eq, SystemLabel, Clone)]
+pub enum MapSystems {
+    StreamLevels,
+}
+
 pub struct CurrentMap(pub Handle<MainLdtk>);
 
 fn load_map(mut commands: Commands, game_assets: Res<GameAssets>) {
     commands.insert_resource(CurrentMap(game_assets.ldtk_map_handle.clone()));
+    commands.insert_resource(CurrentLevel(None));
+}
 
-    commands
-        .spawn_bundle(TileMapBundle {
-            texture_atlas: game_assets.texture_atlas_handle.clone(),
-            ..Default::default()
-        })
-        .insert(CurrentLevel("Level_0".to_string()));
+/// The level the player is currently in
+pub struct CurrentLevel(pub Option<String>);
+
+/// Marks the tilemap of a level that is currently streamed in
+pub struct LoadedLevel(pub String);
+
+/// Marks entities that were spawned for a level, they get removed once it is unloaded
+pub struct LevelMember(pub String);
+
+/// Where an LDtk entity placed in a level sits in the world, in cells
+///
+/// `position_cell` is relative to the level, and is offset by the entity's pivot.
+pub fn entity_position(
+    level_world_position_px: IVec2,
+    position_cell: IVec2,
+    pivot: Vec2,
+    dimensions_px: IVec2,
+) -> Vec2 {
+    position_cell.as_f32()
+        + level_world_position_px.as_f32() / GRID_SIZE as f32
+        + pivot * dimensions_px.as_f32() / GRID_SIZE as f32
 }
 
-pub struct CurrentLevel(pub String);
+#[derive(Debug)]
+pub enum LevelEvent {
+    Loaded(String),
+    Unloaded(String),
+}
 
-fn update_map(
-    mut commands: Commands,
-    mut ldtk_map_asset_events: EventReader<AssetEvent<MainLdtk>>,
+/// The area a level covers in the world, in cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelRect {
+    pub min: IVec2,
+    pub max: IVec2,
+}
+
+impl LevelRect {
+    pub fn new(world_position_px: IVec2, dimensions_px: IVec2) -> Self {
+        let min = world_position_px / GRID_SIZE;
+        LevelRect {
+            min,
+            max: min + dimensions_px / GRID_SIZE,
+        }
+    }
+
+    pub fn contains(&self, cell: IVec2) -> bool {
+        cell.cmpge(self.min).all() && cell.cmplt(self.max).all()
+    }
+
+    /// Whether both rects overlap or share a piece of an edge, rects that only meet at a corner do
+    /// not touch
+    ///
+    /// This matches the `__neighbours` LDtk exports, which the generated level types do not carry.
+    pub fn touches(&self, other: &LevelRect) -> bool {
+        let closed = self.min.cmple(other.max).all() && other.min.cmple(self.max).all();
+        let open = self.min.cmplt(other.max) & other.min.cmplt(self.max);
+
+        closed && open.any()
+    }
+}
+
+/// Switches the `CurrentLevel` once the player walks into a neighbouring level
+fn follow_player_level(
     ldtk_assets: Res<Assets<MainLdtk>>,
     current_map: Res<CurrentMap>,
-    mut ldtk_map_query: Query<(&mut TileMap, &mut Transform, &CurrentLevel)>,
+    player_query: Query<&Position, (With<Player>, Changed<Position>)>,
+    mut current_level: ResMut<CurrentLevel>,
 ) {
-    let mut to_be_updated = vec![];
+    let ldtk = if let Some(LdtkMap { ldtk }) = ldtk_assets.get(&current_map.0) {
+        ldtk
+    } else {
+        return;
+    };
 
-    for event in ldtk_map_asset_events.iter() {
-        match event {
-            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
-                to_be_updated.push(handle.clone());
-            }
-            AssetEvent::Removed { handle: _ } => (),
+    for position in player_query.iter() {
+        let level = if let Some(level) = ldtk.levels.iter().find(|level| {
+            LevelRect::new(level.world_position_px, level.dimensions_px).contains(position.cell)
+        }) {
+            level
+        } else {
+            continue;
+        };
+
+        if current_level.0.as_deref() != Some(&*level.identifier) {
+            info!("Player entered level: {}", level.identifier);
+            current_level.0 = Some(level.identifier.to_string());
         }
     }
+}
 
+/// Keeps the current level and all its neighbours spawned, each as its own tilemap
+fn stream_levels(
+    mut commands: Commands,
+    mut ldtk_map_asset_events: EventReader<AssetEvent<MainLdtk>>,
+    mut level_events: EventWriter<LevelEvent>,
+    ldtk_assets: Res<Assets<MainLdtk>>,
+    current_map: Res<CurrentMap>,
+    current_level: Res<CurrentLevel>,
+    game_assets: Res<GameAssets>,
+    loaded_levels_query: Query<(Entity, &LoadedLevel)>,
+) {
     let ldtk_handle = &current_map.0;
 
-    for handle in to_be_updated {
-        for (mut map, mut transform, current_level) in ldtk_map_query.single_mut() {
-            if &handle != ldtk_handle {
-                continue;
-            }
+    let map_modified = ldtk_map_asset_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle == ldtk_handle,
+        AssetEvent::Removed { handle: _ } => false,
+    });
 
-            let ldtk = if let Some(LdtkMap { ldtk }) = ldtk_assets.get(ldtk_handle) {
-                ldtk
-            } else {
-                continue;
-            };
+    if !map_modified && !current_level.is_changed() {
+        return;
+    }
+
+    let ldtk = if let Some(LdtkMap { ldtk }) = ldtk_assets.get(ldtk_handle) {
+        ldtk
+    } else {
+        return;
+    };
+
+    let current = if let Some(current) = current_level.0.as_ref().and_then(|current| {
+        ldtk.levels
+            .iter()
+            .find(|level| &level.identifier == current)
+    }) {
+        current
+    } else {
+        return;
+    };
 
-            map.clear();
+    let current_rect = LevelRect::new(current.world_position_px, current.dimensions_px);
+    let wanted: Vec<_> = ldtk
+        .levels
+        .iter()
+        .filter(|level| {
+            LevelRect::new(level.world_position_px, level.dimensions_px).touches(&current_rect)
+        })
+        .collect();
+
+    let mut still_loaded = vec![];
 
-            let level = ldtk
-                .levels
+    for (entity, loaded_level) in loaded_levels_query.iter() {
+        // A modified map needs every level to be rebuilt
+        if !map_modified
+            && wanted
                 .iter()
-                .find(|level| level.identifier == current_level.0)
-                .unwrap();
+                .any(|level| level.identifier == loaded_level.0)
+        {
+            still_loaded.push(&loaded_level.0);
+            continue;
+        }
 
-            transform.translation = level.world_position_px.extend(0).as_f32();
+        info!("Unloading level: {}", loaded_level.0);
+        commands.entity(entity).despawn_recursive();
+        level_events.send(LevelEvent::Unloaded(loaded_level.0.clone()));
+    }
+
+    for level in wanted {
+        if still_loaded
+            .iter()
+            .any(|loaded| **loaded == level.identifier)
+        {
+            continue;
+        }
+
+        info!(
+            "Loading level: {} at {}",
+            level.identifier, level.world_position_px
+        );
+
+        let mut map = TileMap::default();
+
+        add_layer(&level.layers.background, 0, &mut map);
+        add_layer(&level.layers.background_details, 1, &mut map);
+        add_layer(&level.layers.foreground, 2, &mut map);
 
-            info!("Map position: {}", level.world_position_px);
+        commands
+            .spawn_bundle(TileMapBundle {
+                texture_atlas: game_assets.texture_atlas_handle.clone(),
+                transform: Transform::from_translation(level.world_position_px.extend(0).as_f32()),
+                ..Default::default()
+            })
+            .insert(map)
+            .insert(LoadedLevel(level.identifier.to_string()));
 
-            add_layer(&level.layers.background, 0, &mut map);
-            add_layer(&level.layers.background_details, 1, &mut map);
-            add_layer(&level.layers.foreground, 2, &mut map);
+        level_events.send(LevelEvent::Loaded(level.identifier.to_string()));
+    }
+
+    commands.insert_resource(LevelBounds(current.dimensions_px / GRID_SIZE));
+}
 
-            commands.insert_resource(LevelBounds(level.dimensions_px / GRID_SIZE));
+/// Removes everything that was spawned for a level once it is unloaded
+///
+/// Runs right after the levels are streamed, so entities spawned for a level that is loaded again
+/// in the same frame are not affected.
+fn despawn_level_members(
+    mut commands: Commands,
+    mut level_events: EventReader<LevelEvent>,
+    member_query: Query<(Entity, &LevelMember)>,
+) {
+    for event in level_events.iter() {
+        if let LevelEvent::Unloaded(level_identifier) = event {
+            for (entity, member) in member_query.iter() {
+                if &member.0 == level_identifier {
+                    commands.entity(entity).despawn_recursive();
+                }
+            }
         }
     }
 }
@@ -8,6 +8,8 @@ use bevy::{
     utils::{HashMap, Instant},
 };
 
+use crate::movement::{Deterministic, TICKS_PER_SECOND};
+
 pub struct MarkerPlugin;
 
 impl Plugin for MarkerPlugin {
@@ -16,42 +18,112 @@ impl Plugin for MarkerPlugin {
     }
 }
 
-fn update_markers(mut marker_query: Query<&mut Markers>) {
+fn update_markers(deterministic: Res<Deterministic>, mut marker_query: Query<&mut Markers>) {
+    // Deterministic markers expire with the movement ticks instead
+    if deterministic.0 {
+        return;
+    }
+
     for mut markers in marker_query.iter_mut() {
         markers.update();
     }
 }
 
+/// How long a marker added during a movement tick has to last to stay set for `seconds` after that
+/// tick
+pub fn after_this_tick(seconds: f32) -> Duration {
+    Duration::from_secs_f64(seconds as f64 + 1. / TICKS_PER_SECOND)
+}
+
 #[derive(Debug)]
 struct MarkerInfo {
     created_at: Instant,
+    created_tick: u64,
     destroy_after: Option<Duration>,
+    value: Option<Box<dyn Any + Send + Sync>>,
 }
 
 #[derive(Debug, Default)]
 pub struct Markers {
     map: HashMap<TypeId, MarkerInfo>,
+    /// How many movement ticks the markers have seen
+    tick: u64,
 }
 
 impl Markers {
+    /// Adds a marker that stays until it is removed
+    pub fn add_marker<T: Any>(&mut self) {
+        self.add(std::any::TypeId::of::<T>(), None, None);
+    }
+
     pub fn add_marker_for<T: Any>(&mut self, destroy_after: Duration) {
-        self.add(std::any::TypeId::of::<T>(), Some(destroy_after));
+        self.add(std::any::TypeId::of::<T>(), Some(destroy_after), None);
+    }
+
+    /// Adds a marker that also carries a value, replacing the value of an existing one
+    pub fn add_value_for<T: Any + Send + Sync>(&mut self, value: T, destroy_after: Duration) {
+        self.add(
+            std::any::TypeId::of::<T>(),
+            Some(destroy_after),
+            Some(Box::new(value)),
+        );
     }
 
     pub fn contains<T: Any>(&self) -> bool {
         self.map.contains_key(&std::any::TypeId::of::<T>())
     }
 
-    fn add(&mut self, marker: TypeId, destroy_after: Option<Duration>) {
+    /// The value of a marker added with [`Markers::add_value_for`]
+    pub fn get<T: Any>(&self) -> Option<&T> {
+        self.map
+            .get(&std::any::TypeId::of::<T>())
+            .and_then(|info| info.value.as_ref())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    pub fn remove<T: Any>(&mut self) {
+        self.map.remove(&std::any::TypeId::of::<T>());
+    }
+
+    fn add(
+        &mut self,
+        marker: TypeId,
+        destroy_after: Option<Duration>,
+        value: Option<Box<dyn Any + Send + Sync>>,
+    ) {
         self.map.insert(
             marker,
             MarkerInfo {
                 created_at: Instant::now(),
+                created_tick: self.tick,
                 destroy_after,
+                value,
             },
         );
     }
 
+    /// Advances the markers by one movement tick, `expire` removes the ones that outlived their
+    /// duration in ticks
+    pub fn tick(&mut self, expire: bool) {
+        self.tick += 1;
+
+        if !expire {
+            return;
+        }
+
+        let tick = self.tick;
+
+        self.map.retain(|_, info| {
+            if let Some(destroy_after) = info.destroy_after.as_ref() {
+                let ticks = (destroy_after.as_secs_f64() * TICKS_PER_SECOND).round() as u64;
+
+                info.created_tick + ticks > tick
+            } else {
+                true
+            }
+        });
+    }
+
     fn update(&mut self) {
         self.map.retain(|_, info| {
             if let Some(destroy_after) = info.destroy_after.as_ref() {
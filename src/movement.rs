@@ -1,10 +1,37 @@
-use std::time::Duration;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    time::Duration,
+};
 
-use bevy::{core::FixedTimestep, prelude::*};
+use bevy::{
+    core::{FixedTimestep, FixedTimesteps},
+    prelude::*,
+    utils::HashMap,
+};
+use serde::Deserialize;
 
-use crate::{ldtk_map::LdtkMap, map::CurrentMap, markers::Markers, MainLdtk, GRID_SIZE};
+use crate::{
+    collision::{CollisionGrid, CollisionKind, CollisionWorld, Mirrored, WithSolids},
+    markers::{after_this_tick, Markers},
+    tuning::PhysicsTuning,
+    GRID_SIZE,
+};
 
-pub struct MovementPlugin;
+/// Adds the movement stages and the systems that move entities
+pub struct MovementPlugin {
+    /// Whether the `Movement` stage ticks `TICKS_PER_SECOND` times a second, otherwise it ticks
+    /// once every time the stage is run, which lets tests step the simulation by hand
+    pub fixed_timestep: bool,
+}
+
+impl Default for MovementPlugin {
+    fn default() -> Self {
+        Self {
+            fixed_timestep: true,
+        }
+    }
+}
 
 #[derive(Debug, Hash, PartialEq, PartialOrd, Eq, Ord, StageLabel, Clone)]
 pub enum MovementStages {
@@ -12,19 +39,100 @@ pub enum MovementStages {
     PostMovement,
 }
 
+/// The phases of a movement tick, they run in the order they are listed
+///
+/// Every system in the `Movement` stage is part of exactly one phase, so a tick always runs its
+/// systems in the same order.
+#[derive(Debug, Hash, PartialEq, Eq, SystemLabel, Clone)]
+pub enum MovementSystems {
+    TickMarkers,
+    LoadFixedPoint,
+    StorePreviousPosition,
+    /// The player's input for this tick is recorded, or played back
+    ReadInput,
+    /// Solid entities like platforms move, carrying and pushing others along
+    MoveSolids,
+    /// Zones and other forces change speeds
+    ApplyForces,
+    /// The player's input changes their speed
+    Control,
+    /// Objects touching the player push them around
+    Interact,
+    ApplyImpulses,
+    ApplySpeed,
+    /// Zones react to where entities ended up
+    ReactToPosition,
+    StoreFixedPoint,
+}
+
+/// The label of the fixed timestep the `Movement` stage runs at
+pub const MOVEMENT_TIMESTEP: &str = "movement";
+
+/// How often the `Movement` stage runs
+///
+/// Speeds, accelerations and friction are all given per tick. They were tuned while speeds were
+/// applied once per rendered frame at 60 fps, this rate keeps that feel on any display.
+pub const TICKS_PER_SECOND: f64 = 60.;
+
 impl Plugin for MovementPlugin {
     fn build(&self, app: &mut App) {
-        app.add_stage_before(
-            CoreStage::Update,
-            MovementStages::Movement,
-            SystemStage::parallel().with_run_criteria(FixedTimestep::steps_per_second(30.)),
-        );
+        let mut movement_stage = SystemStage::parallel();
+
+        if self.fixed_timestep {
+            movement_stage = movement_stage.with_run_criteria(
+                FixedTimestep::steps_per_second(TICKS_PER_SECOND).with_label(MOVEMENT_TIMESTEP),
+            );
+        }
+
+        app.add_stage_before(CoreStage::Update, MovementStages::Movement, movement_stage);
         app.add_stage_after(
             MovementStages::Movement,
             MovementStages::PostMovement,
             SystemStage::single_threaded(),
         )
-        .add_system_to_stage(MovementStages::PostMovement, apply_speed)
+        .add_event::<ContactEvent>()
+        .add_event::<ApplyImpulse>()
+        .init_resource::<PixelSnap>()
+        .init_resource::<Deterministic>()
+        .init_resource::<StateHashes>()
+        .add_system_to_stage(
+            MovementStages::Movement,
+            tick_markers.label(MovementSystems::TickMarkers),
+        )
+        .add_system_to_stage(
+            MovementStages::Movement,
+            load_fixed_point_state
+                .label(MovementSystems::LoadFixedPoint)
+                .after(MovementSystems::TickMarkers),
+        )
+        .add_system_to_stage(
+            MovementStages::Movement,
+            store_previous_position
+                .label(MovementSystems::StorePreviousPosition)
+                .after(MovementSystems::LoadFixedPoint),
+        )
+        .add_system_to_stage(
+            MovementStages::Movement,
+            apply_impulses
+                .label(MovementSystems::ApplyImpulses)
+                .after(MovementSystems::Interact),
+        )
+        .add_system_to_stage(
+            MovementStages::Movement,
+            apply_speed
+                .label(MovementSystems::ApplySpeed)
+                .after(MovementSystems::ApplyImpulses),
+        )
+        .add_system_to_stage(
+            MovementStages::Movement,
+            store_fixed_point_state
+                .label(MovementSystems::StoreFixedPoint)
+                .after(MovementSystems::ReactToPosition),
+        )
+        .add_system_to_stage(
+            MovementStages::Movement,
+            log_state_hash.after(MovementSystems::StoreFixedPoint),
+        )
         .add_system_to_stage(MovementStages::PostMovement, synchronize_to_transform);
     }
 }
@@ -32,31 +140,69 @@ impl Plugin for MovementPlugin {
 #[derive(Debug)]
 pub struct LevelBounds(pub IVec2);
 
-#[derive(Debug, Default)]
+/// Pulls an entity down, or up if the gravity is inverted
+///
+/// Which side counts as ground only depends on whether the gravity points up or down.
+#[derive(Debug, Default, Clone, Copy)]
 pub struct Gravity(pub Vec2);
 
-#[derive(Debug)]
-pub struct Speed {
-    pub speed: Vec2,
-    pub friction: f32,
+impl Gravity {
+    pub fn is_inverted(&self) -> bool {
+        self.0.y > 0.
+    }
+
+    /// The vertical direction away from the ground, `1.` unless the gravity is inverted
+    pub fn up(&self) -> f32 {
+        if self.is_inverted() {
+            -1.
+        } else {
+            1.
+        }
+    }
+
+    pub fn flipped(&self) -> Self {
+        Self(Vec2::new(self.0.x, -self.0.y))
+    }
 }
 
-impl Default for Speed {
+/// How much speed is kept every tick, per axis
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Friction {
+    pub ground: Vec2,
+    pub air: Vec2,
+}
+
+impl Default for Friction {
     fn default() -> Self {
         Self {
-            speed: Default::default(),
-            friction: 0.85,
+            ground: Vec2::splat(0.85),
+            air: Vec2::new(0.9, 1.),
         }
     }
 }
 
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Speed {
+    pub speed: Vec2,
+    pub friction: Friction,
+    /// The fastest gravity makes the entity fall, checked right after gravity is added
+    pub max_fall_speed: Option<f32>,
+}
+
 impl Speed {
     pub fn total_speed(&self) -> Vec2 {
         self.speed
     }
 
-    pub fn apply_friction(&mut self) {
-        self.speed *= self.friction;
+    /// Slows the entity down, `grip` scales how much speed the ground takes away
+    pub fn apply_friction(&mut self, on_ground: bool, grip: f32) {
+        let friction = if on_ground {
+            Vec2::ONE - (Vec2::ONE - self.friction.ground) * grip
+        } else {
+            self.friction.air
+        };
+
+        self.speed *= friction.max(Vec2::ZERO).min(Vec2::ONE);
 
         if self.speed.x.abs() < 0.0005 {
             self.speed.x = 0.;
@@ -96,113 +242,1386 @@ impl Position {
     }
 }
 
+/// An axis aligned box, in cells
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+/// Keeps boxes that merely touch from overlapping
+const EPSILON: f32 = 0.001;
+
+impl Aabb {
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.cmplt(other.max).all() && other.min.cmplt(self.max).all()
+    }
+
+    /// All cells the box overlaps, cells that only touch its edges are not included
+    pub fn cells(&self) -> impl Iterator<Item = IVec2> {
+        let min = (self.min + Vec2::splat(EPSILON)).floor().as_i32();
+        let max = (self.max - Vec2::splat(EPSILON)).floor().as_i32();
+
+        (min.y..=max.y).flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+    }
+}
+
+/// The box an entity occupies, in cells
+///
+/// The entity's `Position` sits at the bottom center of the box.
+#[derive(Debug, Clone, Copy)]
+pub struct Collider {
+    pub size: Vec2,
+}
+
+impl Default for Collider {
+    fn default() -> Self {
+        Self { size: Vec2::ONE }
+    }
+}
+
+impl Collider {
+    pub fn from_dimensions_px(dimensions_px: IVec2) -> Self {
+        Self {
+            size: dimensions_px.as_f32() / GRID_SIZE as f32,
+        }
+    }
+
+    pub fn aabb(&self, position: &Position) -> Aabb {
+        self.aabb_at(position.total_position())
+    }
+
+    fn aabb_at(&self, bottom_center: Vec2) -> Aabb {
+        Aabb {
+            min: bottom_center - Vec2::new(self.size.x / 2., 0.),
+            max: bottom_center + Vec2::new(self.size.x / 2., self.size.y),
+        }
+    }
+}
+
+/// Marks entities whose collider blocks moving entities, like it was a solid tile
+#[derive(Debug, Default)]
+pub struct Solid;
+
+/// Where the entity was before the last movement tick, used to smooth out rendering
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PreviousPosition(pub Position);
+
+/// Whether the simulation reproduces the exact same run from the same inputs
+///
+/// Positions and speeds are kept as fixed point integers between ticks, markers expire after a
+/// number of ticks instead of wall clock time, the player's input is recorded every tick and a
+/// hash of the movement state is logged every tick to compare runs. Opt in by setting the
+/// `DETERMINISTIC` environment variable.
+///
+/// The maths within a tick still runs in `f32`, so runs are only guaranteed to match on the same
+/// build and machine, not across machines.
+#[derive(Debug)]
+pub struct Deterministic(pub bool);
+
+impl Default for Deterministic {
+    fn default() -> Self {
+        Self(std::env::var_os("DETERMINISTIC").is_some())
+    }
+}
+
+/// The resolution of deterministic positions and speeds, in steps per cell
+const FIXED_POINT_SCALE: i64 = 1 << 16;
+
+/// The movement state of an entity in deterministic mode, as fixed point integers
+///
+/// Every tick loads `Position` and `Speed` from it and stores them back at its end, so no floating
+/// point rounding below the fixed point resolution carries over from one tick to the next. Within
+/// the tick they are still `f32`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedPointState {
+    /// In steps of `1 / FIXED_POINT_SCALE` cells
+    position: [i64; 2],
+    /// In steps of `1 / FIXED_POINT_SCALE` cells per tick
+    speed: [i64; 2],
+}
+
+impl FixedPointState {
+    fn new(position: &Position, speed: Option<&Speed>) -> Self {
+        let to_fixed = |value: f32| (value * FIXED_POINT_SCALE as f32).round() as i64;
+        let speed = speed.map_or(Vec2::ZERO, |speed| speed.speed);
+
+        Self {
+            position: [
+                position.cell.x as i64 * FIXED_POINT_SCALE + to_fixed(position.fraction.x),
+                position.cell.y as i64 * FIXED_POINT_SCALE + to_fixed(position.fraction.y),
+            ],
+            speed: [to_fixed(speed.x), to_fixed(speed.y)],
+        }
+    }
+
+    fn position(&self) -> Position {
+        let cell = |value: i64| value.div_euclid(FIXED_POINT_SCALE) as i32;
+        let fraction =
+            |value: i64| value.rem_euclid(FIXED_POINT_SCALE) as f32 / FIXED_POINT_SCALE as f32;
+
+        Position {
+            cell: IVec2::new(cell(self.position[0]), cell(self.position[1])),
+            fraction: Vec2::new(fraction(self.position[0]), fraction(self.position[1])),
+        }
+    }
+
+    fn speed(&self) -> Vec2 {
+        Vec2::new(self.speed[0] as f32, self.speed[1] as f32) / FIXED_POINT_SCALE as f32
+    }
+}
+
+/// The state hash of every movement tick so far, only kept in deterministic mode
+#[derive(Debug, Default)]
+pub struct StateHashes(pub Vec<u64>);
+
+/// Whether rendered positions get rounded to whole pixels
+#[derive(Debug)]
+pub struct PixelSnap(pub bool);
+
+impl Default for PixelSnap {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
 #[derive(Debug)]
 pub struct OnGround;
 
-fn apply_speed(
-    current_map: Res<CurrentMap>,
-    ldtk_assets: Res<Assets<MainLdtk>>,
-    mut movement_query: Query<(&mut Position, &mut Speed, Option<&Gravity>, &mut Markers)>,
-) {
-    let ldtk = if let Some(LdtkMap { ldtk }) = ldtk_assets.get(&current_map.0) {
-        ldtk
+/// Set for the coyote time after the entity last touched the ground, it can still jump until it is
+/// gone
+#[derive(Debug)]
+pub struct CoyoteTime;
+
+#[derive(Debug)]
+pub struct InWater;
+
+/// Set while the center of the entity is inside a climbable tile
+#[derive(Debug)]
+pub struct OnClimbable;
+
+/// Set while the entity holds on to a climbable tile, which suspends its gravity
+#[derive(Debug)]
+pub struct Climbing;
+
+/// Set while the entity is pushing against a wall, on the given side
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OnWall(pub WallSide);
+
+/// How entities behave while submerged in a liquid tile
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct WaterPhysics {
+    /// Multiplied with the entity's gravity
+    pub gravity_scale: f32,
+    /// Pushes against gravity, relative to the entity's gravity
+    pub buoyancy: f32,
+    /// Additional friction on both axes
+    pub drag: f32,
+    /// Upward speed gained by a swim stroke
+    pub stroke: f32,
+}
+
+impl Default for WaterPhysics {
+    fn default() -> Self {
+        Self {
+            gravity_scale: 0.3,
+            buoyancy: 0.2,
+            drag: 0.9,
+            stroke: 0.25,
+        }
+    }
+}
+
+/// Which sides touched a blocking tile during a [`step`]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Contacts {
+    /// Set while standing on ground, not only when landing
+    pub ground: bool,
+    pub ceiling: bool,
+    pub wall_left: bool,
+    pub wall_right: bool,
+    /// Whether the entity ended up inside a liquid tile
+    pub in_water: bool,
+    /// The speed the entity had when it hit something, per axis
+    pub impact: Vec2,
+    /// The kind of tile at the center of the entity
+    pub tile: CollisionKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WallSide {
+    Left,
+    Right,
+}
+
+/// Set while an entity ignores its own input, after being knocked back
+#[derive(Debug)]
+pub struct InputLocked;
+
+/// How an [`ApplyImpulse`] changes the speed of its entity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImpulseMode {
+    /// Adds to the current speed
+    Add,
+    /// Replaces the current speed
+    Override,
+}
+
+/// Pushes an entity, every push in the game goes through this event
+///
+/// Impulses are applied in the `Movement` stage before the speeds are, send them from systems that
+/// run before [`MovementSystems::ApplyImpulses`] in that stage.
+#[derive(Debug, Clone, Copy)]
+pub struct ApplyImpulse {
+    pub entity: Entity,
+    pub impulse: Vec2,
+    pub mode: ImpulseMode,
+    /// How long the entity ignores its own input afterwards
+    pub lockout: Option<Duration>,
+}
+
+impl ApplyImpulse {
+    pub fn add(entity: Entity, impulse: Vec2) -> Self {
+        Self {
+            entity,
+            impulse,
+            mode: ImpulseMode::Add,
+            lockout: None,
+        }
+    }
+
+    pub fn set(entity: Entity, speed: Vec2) -> Self {
+        Self {
+            mode: ImpulseMode::Override,
+            ..Self::add(entity, speed)
+        }
+    }
+
+    /// Throws the entity into `direction`, taking away its control for `lockout`
+    pub fn knockback(entity: Entity, direction: Vec2, strength: f32, lockout: Duration) -> Self {
+        Self::set(entity, direction.normalize_or_zero() * strength).with_lockout(lockout)
+    }
+
+    pub fn with_lockout(self, lockout: Duration) -> Self {
+        Self {
+            lockout: Some(lockout),
+            ..self
+        }
+    }
+}
+
+/// Sent by the movement stage whenever an entity touches the world
+#[derive(Debug, Clone, Copy)]
+pub enum ContactEvent {
+    /// The entity touched ground after being in the air, `speed` is its vertical speed
+    Landed {
+        entity: Entity,
+        speed: f32,
+    },
+    HitWall {
+        entity: Entity,
+        side: WallSide,
+        speed: f32,
+    },
+    HitCeiling {
+        entity: Entity,
+        speed: f32,
+    },
+    /// A moving platform could not push the entity out of its way, the platform waits until it is
+    /// free
+    Crushed {
+        entity: Entity,
+    },
+    EnteredTile {
+        entity: Entity,
+        kind: CollisionKind,
+    },
+    LeftTile {
+        entity: Entity,
+        kind: CollisionKind,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct StepResult {
+    pub position: Position,
+    pub speed: Speed,
+    pub contacts: Contacts,
+}
+
+/// Advances a single entity by one movement tick
+///
+/// Gravity is only applied while the entity is not `on_ground`, and is weakened by `water` while
+/// the entity is submerged.
+pub fn step(
+    grid: &impl CollisionGrid,
+    position: Position,
+    mut speed: Speed,
+    collider: &Collider,
+    gravity: Option<&Gravity>,
+    on_ground: bool,
+    water: &WaterPhysics,
+) -> StepResult {
+    let mut contacts = Contacts::default();
+    let mut pos = position.total_position();
+
+    let is_submerged = |pos: Vec2| {
+        collider
+            .aabb_at(pos)
+            .cells()
+            .any(|cell| grid.kind_at(cell) == CollisionKind::Liquid)
+    };
+
+    let submerged = is_submerged(pos);
+
+    if let Some(gravity) = gravity {
+        if submerged {
+            speed.speed += gravity.0 * (water.gravity_scale - water.buoyancy);
+        } else if !on_ground {
+            speed.speed += gravity.0;
+        }
+    }
+
+    if let Some(max_fall_speed) = speed.max_fall_speed {
+        speed.speed.y = speed.speed.y.max(-max_fall_speed);
+    }
+
+    let delta = speed.total_speed();
+
+    // Only grounded entities can step up, so walls stay walls while jumping
+    let step_height = if on_ground { MAX_STEP } else { 0. };
+
+    if delta.x != 0. {
+        if let Some(hit) = sweep_x(grid, collider, &mut pos, delta.x, step_height) {
+            speed.speed.x = 0.;
+            contacts.impact.x = delta.x;
+
+            match hit {
+                Hit::Positive => contacts.wall_right = true,
+                Hit::Negative => contacts.wall_left = true,
+            }
+        }
+    }
+
+    if delta.y != 0. {
+        if let Some(hit) = sweep_y(grid, collider, &mut pos, delta.y) {
+            speed.speed.y = 0.;
+            contacts.impact.y = delta.y;
+
+            match hit {
+                Hit::Positive => contacts.ceiling = true,
+                Hit::Negative => contacts.ground = true,
+            }
+        }
+    }
+
+    if !contacts.ground && speed.speed.y <= 0. {
+        contacts.ground =
+            settle_on_slope(grid, &mut pos, on_ground) || is_standing(grid, collider, pos);
+    }
+
+    contacts.in_water = is_submerged(pos);
+    contacts.tile = grid.kind_at((pos + Vec2::new(0., collider.size.y / 2.)).floor().as_i32());
+
+    if submerged {
+        speed.speed *= water.drag;
+    }
+
+    let grip = if contacts.ground {
+        grid.grip_at(IVec2::new(pos.x.floor() as i32, pos.y.round() as i32 - 1))
     } else {
-        return;
+        1.
     };
 
-    for (mut position, mut speed, gravity, mut markers) in movement_query.iter_mut() {
-        let level = if let Some(level) = ldtk.levels.iter().find(|level| {
-            let level_bl = level.world_position_px / GRID_SIZE;
-            let level_tr = level_bl + level.dimensions_px / GRID_SIZE;
-            position.cell.ge(&level_bl) && position.cell.lt(&level_tr)
-        }) {
-            level
-        } else {
-            error!("Could not find associated level for entity.");
-            continue;
+    speed.apply_friction(contacts.ground, grip);
+
+    StepResult {
+        position: Position::from(pos),
+        speed,
+        contacts,
+    }
+}
+
+/// Advances an entity whose gravity pulls upwards, by stepping it through the world turned upside
+/// down
+///
+/// The ground in the returned contacts is the side the gravity pulls towards.
+pub fn step_inverted(
+    grid: &impl CollisionGrid,
+    position: Position,
+    speed: Speed,
+    collider: &Collider,
+    gravity: Option<&Gravity>,
+    on_ground: bool,
+    water: &WaterPhysics,
+) -> StepResult {
+    // Turning the box upside down swaps its bottom and top
+    let mirror_position = |position: Position| {
+        Vec2::new(1., -1.) * position.total_position() - Vec2::new(0., collider.size.y)
+    };
+    let mirror_speed = |mut speed: Speed| {
+        speed.speed.y = -speed.speed.y;
+        speed
+    };
+
+    let mut result = step(
+        &Mirrored::new(grid),
+        Position::from(mirror_position(position)),
+        mirror_speed(speed),
+        collider,
+        gravity.map(Gravity::flipped).as_ref(),
+        on_ground,
+        water,
+    );
+
+    result.position = Position::from(mirror_position(result.position));
+    result.speed = mirror_speed(result.speed);
+    result.contacts.impact.y = -result.contacts.impact.y;
+
+    result
+}
+
+/// Moves an entity by `delta` without gravity or friction, stopping at anything in the way
+///
+/// Used for entities that are pushed or carried along, returns where the entity ended up and
+/// whether it was stopped before covering all of `delta`.
+pub fn slide(
+    grid: &impl CollisionGrid,
+    position: Position,
+    collider: &Collider,
+    delta: Vec2,
+) -> (Position, bool) {
+    let mut pos = position.total_position();
+    let mut blocked = false;
+
+    if delta.x != 0. {
+        blocked |= sweep_x(grid, collider, &mut pos, delta.x, 0.).is_some();
+    }
+
+    if delta.y != 0. {
+        blocked |= sweep_y(grid, collider, &mut pos, delta.y).is_some();
+    }
+
+    (Position::from(pos), blocked)
+}
+
+/// On which side along the swept axis a tile was hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Hit {
+    Positive,
+    Negative,
+}
+
+/// How far an entity walking along a slope is moved up or down to stay on its surface
+const MAX_STEP: f32 = 0.5;
+
+/// Keeps the bottom center of an entity on the surface of the slope it walks along
+///
+/// Walking up pushes the entity into the slope and onto the tile at its top, walking down would
+/// leave it hovering above the slope if it was not pulled down. Returns whether the entity is now
+/// standing on the slope.
+fn settle_on_slope(grid: &impl CollisionGrid, pos: &mut Vec2, on_ground: bool) -> bool {
+    let center = IVec2::new(pos.x.floor() as i32, (pos.y + EPSILON).floor() as i32);
+    let x = pos.x - pos.x.floor();
+
+    if grid.kind_at(center) == CollisionKind::Solid {
+        let top = (center.y + 1) as f32;
+
+        if top - pos.y <= MAX_STEP + EPSILON {
+            pos.y = top;
+            return true;
+        }
+
+        return false;
+    }
+
+    for cell in [center, center - IVec2::Y].iter() {
+        if let CollisionKind::Slope(slope) = grid.kind_at(*cell) {
+            let surface = cell.y as f32 + slope.height_at(x);
+
+            let snaps = if surface > pos.y {
+                surface - pos.y <= MAX_STEP
+            } else {
+                on_ground && pos.y - surface <= MAX_STEP
+            };
+
+            if snaps {
+                pos.y = surface;
+            }
+
+            return snaps;
+        }
+    }
+
+    false
+}
+
+/// Whether the bottom of the box rests directly on a solid tile, a one way platform or a solid
+/// entity
+fn is_standing(grid: &impl CollisionGrid, collider: &Collider, pos: Vec2) -> bool {
+    let aabb = collider.aabb_at(pos);
+
+    let on_solid = grid.solids().iter().any(|solid| {
+        (solid.max.y - aabb.min.y).abs() <= EPSILON
+            && solid.min.x < aabb.max.x
+            && aabb.min.x < solid.max.x
+    });
+
+    if on_solid {
+        return true;
+    }
+
+    if (pos.y - pos.y.round()).abs() > EPSILON {
+        return false;
+    }
+
+    let below = pos.y.round() as i32 - 1;
+
+    covered_cells(aabb.min.x, aabb.max.x).any(|x| {
+        matches!(
+            grid.kind_at(IVec2::new(x, below)),
+            CollisionKind::Solid | CollisionKind::OneWay
+        )
+    })
+}
+
+/// The cells a box covers between `min` and `max` along one axis
+fn covered_cells(min: f32, max: f32) -> std::ops::RangeInclusive<i32> {
+    (min + EPSILON).floor() as i32..=(max - EPSILON).floor() as i32
+}
+
+/// The limit closest to where a sweep by `delta` started
+fn nearest_limit(delta: f32, limits: impl Iterator<Item = f32>) -> Option<f32> {
+    limits.fold(None, |nearest, limit| match nearest {
+        Some(nearest) if delta > 0. => Some(limit.min(nearest)),
+        Some(nearest) => Some(limit.max(nearest)),
+        None => Some(limit),
+    })
+}
+
+/// Moves `pos` horizontally by `delta`, stopping at the first solid column or entity in the way
+///
+/// Every column between the start and the end is checked, so no speed is fast enough to skip
+/// over a wall. Tiles whose top is at most `step_height` above the entity's bottom do not count
+/// as walls, so entities can walk up slopes onto the tile at their top. A slope blocks like a wall
+/// of the height of the edge the entity moves into, so its tall side cannot be walked through.
+fn sweep_x(
+    grid: &impl CollisionGrid,
+    collider: &Collider,
+    pos: &mut Vec2,
+    delta: f32,
+    step_height: f32,
+) -> Option<Hit> {
+    let aabb = collider.aabb_at(*pos);
+    let half_width = collider.size.x / 2.;
+    let rows = covered_cells(aabb.min.y, aabb.max.y);
+    // Moving right enters tiles through their left edge, and the other way around
+    let entered_edge = if delta > 0. { 0. } else { 1. };
+    let is_wall = |x: i32| {
+        rows.clone().any(|y| {
+            let height = match grid.kind_at(IVec2::new(x, y)) {
+                CollisionKind::Solid => 1.,
+                CollisionKind::Slope(slope) => slope.height_at(entered_edge),
+                _ => 0.,
+            };
+
+            height > 0. && y as f32 + height > aabb.min.y + step_height + EPSILON
+        })
+    };
+
+    let tile_limit = if delta > 0. {
+        let first = (aabb.max.x - EPSILON).floor() as i32 + 1;
+        let last = (aabb.max.x + delta - EPSILON).floor() as i32;
+
+        (first..=last)
+            .find(|x| is_wall(*x))
+            .map(|wall| wall as f32 - half_width)
+    } else {
+        let first = (aabb.min.x + EPSILON).floor() as i32 - 1;
+        let last = (aabb.min.x + delta + EPSILON).floor() as i32;
+
+        (last..=first)
+            .rev()
+            .find(|x| is_wall(*x))
+            .map(|wall| (wall + 1) as f32 + half_width)
+    };
+
+    let solid_limits = grid
+        .solids()
+        .iter()
+        .filter(|solid| solid.min.y < aabb.max.y - EPSILON && aabb.min.y + EPSILON < solid.max.y)
+        .filter_map(|solid| {
+            if delta > 0. && aabb.max.x <= solid.min.x + EPSILON && aabb.max.x + delta > solid.min.x
+            {
+                Some(solid.min.x - half_width)
+            } else if delta < 0.
+                && aabb.min.x >= solid.max.x - EPSILON
+                && aabb.min.x + delta < solid.max.x
+            {
+                Some(solid.max.x + half_width)
+            } else {
+                None
+            }
+        });
+
+    match nearest_limit(delta, tile_limit.into_iter().chain(solid_limits)) {
+        Some(limit) => {
+            pos.x = limit;
+
+            if delta > 0. {
+                Some(Hit::Positive)
+            } else {
+                Some(Hit::Negative)
+            }
+        }
+        None => {
+            pos.x += delta;
+            None
+        }
+    }
+}
+
+/// Moves `pos` vertically by `delta`, stopping at the first blocking row or entity in the way
+///
+/// Falling entities land on one way platforms, as every row they sweep through was below them,
+/// and on the surface of slopes below their center.
+fn sweep_y(
+    grid: &impl CollisionGrid,
+    collider: &Collider,
+    pos: &mut Vec2,
+    delta: f32,
+) -> Option<Hit> {
+    let aabb = collider.aabb_at(*pos);
+    let columns = covered_cells(aabb.min.x, aabb.max.x);
+
+    let tile_limit = if delta > 0. {
+        let first = (aabb.max.y - EPSILON).floor() as i32 + 1;
+        let last = (aabb.max.y + delta - EPSILON).floor() as i32;
+        // Slopes are solid below their surface, so their underside blocks like a solid tile's
+        let is_ceiling = |y: i32| {
+            columns.clone().any(|x| {
+                matches!(
+                    grid.kind_at(IVec2::new(x, y)),
+                    CollisionKind::Solid | CollisionKind::Slope(_)
+                )
+            })
         };
 
-        let col_layer = &level.layers.foreground;
-        let collision = match &col_layer.special {
-            bevy_spicy_ldtk::SpecialValues::IntGrid { values, .. } => values,
-            _ => return,
+        (first..=last)
+            .find(|y| is_ceiling(*y))
+            .map(|ceiling| ceiling as f32 - collider.size.y)
+    } else {
+        let current = (aabb.min.y + EPSILON).floor() as i32;
+        let last = (aabb.min.y + delta + EPSILON).floor() as i32;
+        let is_ground = |y: i32| {
+            columns.clone().any(|x| {
+                matches!(
+                    grid.kind_at(IVec2::new(x, y)),
+                    CollisionKind::Solid | CollisionKind::OneWay
+                )
+            })
         };
+        let (center, x) = (pos.x.floor() as i32, pos.x - pos.x.floor());
+        let slope_surface = |y: i32| match grid.kind_at(IVec2::new(center, y)) {
+            CollisionKind::Slope(slope) => Some(y as f32 + slope.height_at(x)),
+            _ => None,
+        };
+
+        (last..=current).rev().find_map(|y| {
+            // The row the entity is already in can only hold a slope to land on
+            if y < current && is_ground(y) {
+                return Some((y + 1) as f32);
+            }
 
-        let has_collision = |mut pos: IVec2| -> bool {
-            pos -= level.world_position_px / GRID_SIZE;
-            let idx = pos.y * col_layer.dimensions_cell.x as i32 + pos.x;
+            slope_surface(y).filter(|surface| {
+                *surface <= aabb.min.y + EPSILON && *surface >= aabb.min.y + delta
+            })
+        })
+    };
 
-            if pos.x >= col_layer.dimensions_cell.x as i32
-                || pos.x < 0
-                || pos.y >= col_layer.dimensions_cell.y as i32
-                || pos.y < 0
+    let solid_limits = grid
+        .solids()
+        .iter()
+        .filter(|solid| solid.min.x < aabb.max.x - EPSILON && aabb.min.x + EPSILON < solid.max.x)
+        .filter_map(|solid| {
+            if delta > 0. && aabb.max.y <= solid.min.y + EPSILON && aabb.max.y + delta > solid.min.y
             {
-                return true;
+                Some(solid.min.y - collider.size.y)
+            } else if delta < 0.
+                && aabb.min.y >= solid.max.y - EPSILON
+                && aabb.min.y + delta < solid.max.y
+            {
+                Some(solid.max.y)
+            } else {
+                None
             }
+        });
 
-            collision[idx as usize] == 1
-        };
+    match nearest_limit(delta, tile_limit.into_iter().chain(solid_limits)) {
+        Some(limit) => {
+            pos.y = limit;
 
-        if let Some(gravity) = gravity.as_ref() {
-            if !markers.contains::<OnGround>() {
-                speed.speed += gravity.0;
+            if delta > 0. {
+                Some(Hit::Positive)
+            } else {
+                Some(Hit::Negative)
             }
         }
+        None => {
+            pos.y += delta;
+            None
+        }
+    }
+}
 
-        let steps = {
-            let abs_speed = speed.total_speed().abs();
+/// What an entity touched in its previous step
+#[derive(Debug, Clone, Copy)]
+struct PreviousContact {
+    ground: bool,
+    ceiling: bool,
+    wall_left: bool,
+    wall_right: bool,
+    tile: CollisionKind,
+}
 
-            (abs_speed.x + abs_speed.y * 5.).ceil()
-        } as u32;
+fn tick_markers(deterministic: Res<Deterministic>, mut marker_query: Query<&mut Markers>) {
+    for mut markers in marker_query.iter_mut() {
+        markers.tick(deterministic.0);
+    }
+}
 
-        // info!("Pos was: {}", position.total_position());
-        for _ in 0..steps {
-            position.fraction += speed.total_speed() / steps as f32;
+/// Replaces positions and speeds with their exact fixed point values, in deterministic mode
+fn load_fixed_point_state(
+    deterministic: Res<Deterministic>,
+    mut state_query: Query<(&FixedPointState, &mut Position, Option<&mut Speed>)>,
+) {
+    if !deterministic.0 {
+        return;
+    }
 
-            if speed.total_speed().x != 0. {
-                // Check for x collision
-                if has_collision(position.cell + IVec2::X) && position.fraction.x >= 0.8 {
-                    position.fraction.x = 0.8;
-                    speed.speed.x = 0.;
-                }
+    for (state, mut position, speed) in state_query.iter_mut() {
+        *position = state.position();
 
-                if has_collision(position.cell - IVec2::X) && position.fraction.x <= 0.2 {
-                    position.fraction.x = 0.2;
-                    speed.speed.x = 0.;
-                }
-            }
+        if let Some(mut speed) = speed {
+            speed.speed = state.speed();
+        }
+    }
+}
 
-            if speed.total_speed().y != 0. {
-                // Check for y collision
-                // Ground
-                if has_collision(position.cell - IVec2::Y) && position.fraction.y <= 0.0 {
-                    position.fraction.y = 0.0;
-                    speed.speed.y = 0.;
+/// Rounds positions and speeds to fixed point values and keeps them for the next tick, in
+/// deterministic mode
+fn store_fixed_point_state(
+    mut commands: Commands,
+    deterministic: Res<Deterministic>,
+    mut state_query: Query<(
+        Entity,
+        &mut Position,
+        Option<&mut Speed>,
+        Option<&mut FixedPointState>,
+    )>,
+) {
+    if !deterministic.0 {
+        return;
+    }
+
+    for (entity, mut position, speed, state) in state_query.iter_mut() {
+        let new_state = FixedPointState::new(&position, speed.as_deref());
 
-                    markers.add_marker_for::<OnGround>(Duration::from_millis(50));
-                }
+        *position = new_state.position();
+
+        if let Some(mut speed) = speed {
+            speed.speed = new_state.speed();
+        }
 
-                // Top
-                if has_collision(position.cell + IVec2::Y) && position.fraction.y >= 0.2 {
-                    position.fraction.y = 0.2;
-                    speed.speed.y = 0.;
-                }
+        match state {
+            Some(mut state) => *state = new_state,
+            None => {
+                commands.entity(entity).insert(new_state);
             }
+        }
+    }
+}
+
+/// Logs a hash of every fixed point state, runs with the same inputs log the same hashes
+fn log_state_hash(
+    deterministic: Res<Deterministic>,
+    mut state_hashes: ResMut<StateHashes>,
+    state_query: Query<&FixedPointState>,
+) {
+    if !deterministic.0 {
+        return;
+    }
+
+    let mut hasher = DefaultHasher::new();
+
+    for state in state_query.iter() {
+        state.hash(&mut hasher);
+    }
 
-            position.normalize();
-            // info!("Player position: {:?}", position);
+    let hash = hasher.finish();
+    state_hashes.0.push(hash);
+
+    debug!(
+        "Movement tick {}: state hash {:016x}",
+        state_hashes.0.len(),
+        hash
+    );
+}
+
+fn apply_impulses(
+    mut impulse_events: EventReader<ApplyImpulse>,
+    mut speed_query: Query<(&mut Speed, Option<&mut Markers>)>,
+) {
+    for event in impulse_events.iter() {
+        let (mut speed, markers) = if let Ok(entity) = speed_query.get_mut(event.entity) {
+            entity
+        } else {
+            continue;
+        };
+
+        match event.mode {
+            ImpulseMode::Add => speed.speed += event.impulse,
+            ImpulseMode::Override => speed.speed = event.impulse,
+        }
+
+        if let (Some(lockout), Some(mut markers)) = (event.lockout, markers) {
+            markers.add_marker_for::<InputLocked>(lockout);
         }
+    }
+}
+
+fn store_previous_position(mut position_query: Query<(&Position, &mut PreviousPosition)>) {
+    for (position, mut previous_position) in position_query.iter_mut() {
+        previous_position.0 = *position;
+    }
+}
+
+fn apply_speed(
+    mut contact_events: EventWriter<ContactEvent>,
+    mut previous_contacts: Local<HashMap<Entity, PreviousContact>>,
+    collision_world: Res<CollisionWorld>,
+    tuning: Res<PhysicsTuning>,
+    solids_query: Query<(&Position, &Collider), With<Solid>>,
+    mut movement_query: Query<
+        (
+            Entity,
+            &mut Position,
+            &mut Speed,
+            &Collider,
+            Option<&Gravity>,
+            &mut Markers,
+        ),
+        Without<Solid>,
+    >,
+) {
+    let solids: Vec<_> = solids_query
+        .iter()
+        .map(|(position, collider)| collider.aabb(position))
+        .collect();
+
+    let grid = WithSolids {
+        grid: &*collision_world,
+        solids: &solids,
+    };
+
+    let mut contacts = HashMap::default();
+
+    for (entity, mut position, mut speed, collider, gravity, mut markers) in
+        movement_query.iter_mut()
+    {
+        let gravity_pull = gravity.filter(|_| !markers.contains::<Climbing>());
+        let on_ground = markers.contains::<OnGround>();
+
+        let result = if gravity.map_or(false, Gravity::is_inverted) {
+            step_inverted(
+                &grid,
+                *position,
+                *speed,
+                collider,
+                gravity_pull,
+                on_ground,
+                &tuning.water,
+            )
+        } else {
+            step(
+                &grid,
+                *position,
+                *speed,
+                collider,
+                gravity_pull,
+                on_ground,
+                &tuning.water,
+            )
+        };
+
+        if result.contacts.ground {
+            markers.add_marker_for::<OnGround>(Duration::from_millis(50));
+            // Refreshed by actual contact only, `OnGround` lingers after leaving the ground
+            markers.add_marker_for::<CoyoteTime>(after_this_tick(tuning.coyote_time));
+        }
+
+        if result.contacts.in_water {
+            markers.add_marker_for::<InWater>(Duration::from_millis(50));
+        }
+
+        if result.contacts.tile == CollisionKind::Climbable {
+            markers.add_marker_for::<OnClimbable>(Duration::from_millis(50));
+        }
+
+        if result.contacts.wall_left {
+            markers.add_value_for(OnWall(WallSide::Left), Duration::from_millis(50));
+        } else if result.contacts.wall_right {
+            markers.add_value_for(OnWall(WallSide::Right), Duration::from_millis(50));
+        }
+
+        *position = result.position;
+        *speed = result.speed;
+
+        send_contact_events(
+            entity,
+            &result.contacts,
+            previous_contacts.get(&entity),
+            &mut contact_events,
+        );
+
+        contacts.insert(
+            entity,
+            PreviousContact {
+                ground: result.contacts.ground,
+                ceiling: result.contacts.ceiling,
+                wall_left: result.contacts.wall_left,
+                wall_right: result.contacts.wall_right,
+                tile: result.contacts.tile,
+            },
+        );
+    }
+
+    // Only keep entities that are still around
+    *previous_contacts = contacts;
+}
 
-        speed.apply_friction();
+fn send_contact_events(
+    entity: Entity,
+    contacts: &Contacts,
+    previous: Option<&PreviousContact>,
+    contact_events: &mut EventWriter<ContactEvent>,
+) {
+    // Contacts that last over several ticks, like pushing against a wall, are only sent once
+    let started =
+        |now: bool, before: fn(&PreviousContact) -> bool| now && !previous.map_or(false, before);
+
+    if started(contacts.ground, |previous| previous.ground) {
+        contact_events.send(ContactEvent::Landed {
+            entity,
+            speed: contacts.impact.y,
+        });
+    }
+
+    if started(contacts.wall_left, |previous| previous.wall_left) {
+        contact_events.send(ContactEvent::HitWall {
+            entity,
+            side: WallSide::Left,
+            speed: contacts.impact.x,
+        });
+    }
+
+    if started(contacts.wall_right, |previous| previous.wall_right) {
+        contact_events.send(ContactEvent::HitWall {
+            entity,
+            side: WallSide::Right,
+            speed: contacts.impact.x,
+        });
+    }
+
+    if started(contacts.ceiling, |previous| previous.ceiling) {
+        contact_events.send(ContactEvent::HitCeiling {
+            entity,
+            speed: contacts.impact.y,
+        });
+    }
+
+    match previous {
+        Some(previous) if previous.tile == contacts.tile => (),
+        Some(previous) => {
+            contact_events.send(ContactEvent::LeftTile {
+                entity,
+                kind: previous.tile,
+            });
+            contact_events.send(ContactEvent::EnteredTile {
+                entity,
+                kind: contacts.tile,
+            });
+        }
+        None => contact_events.send(ContactEvent::EnteredTile {
+            entity,
+            kind: contacts.tile,
+        }),
     }
 }
 
+/// Places entities between their previous and current position, by how far the clock has
+/// advanced into the next movement tick
 fn synchronize_to_transform(
-    mut movement_query: Query<(&mut Transform, &Position), Changed<Position>>,
+    fixed_timesteps: Res<FixedTimesteps>,
+    pixel_snap: Res<PixelSnap>,
+    mut movement_query: Query<(
+        &mut Transform,
+        &Position,
+        Option<&PreviousPosition>,
+        Option<(&Gravity, &Collider)>,
+    )>,
 ) {
-    for (mut transform, position) in movement_query.iter_mut() {
-        transform.translation = (position.total_position() * crate::GRID_SIZE as f32)
-            .round()
-            .extend(transform.translation.z);
+    let overstep = fixed_timesteps
+        .get(MOVEMENT_TIMESTEP)
+        .map_or(1., |timestep| timestep.overstep_percentage() as f32)
+        .min(1.);
+
+    for (mut transform, position, previous_position, gravity) in movement_query.iter_mut() {
+        let position = match previous_position {
+            Some(previous_position) => previous_position
+                .0
+                .total_position()
+                .lerp(position.total_position(), overstep),
+            None => position.total_position(),
+        };
+
+        let mut translation = position * crate::GRID_SIZE as f32;
+
+        if pixel_snap.0 {
+            translation = translation.round();
+        }
+
+        // Upside down entities are flipped around the center of their box, so their feet stay on
+        // the ground they stand on
+        match gravity {
+            Some((gravity, collider)) if gravity.is_inverted() => {
+                transform.scale.y = -transform.scale.y.abs();
+                translation.y += collider.size.y * crate::GRID_SIZE as f32;
+            }
+            _ => transform.scale.y = transform.scale.y.abs(),
+        }
+
+        transform.translation = translation.extend(transform.translation.z);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collision::Slope;
+
+    fn collider() -> Collider {
+        Collider {
+            size: Vec2::new(0.4, 0.9),
+        }
+    }
+
+    fn gravity() -> Gravity {
+        Gravity(Vec2::new(0., -0.02))
+    }
+
+    /// Air friction is turned off, so speeds after a step are exact
+    fn with_speed(speed: Vec2) -> Speed {
+        Speed {
+            speed,
+            friction: Friction {
+                ground: Vec2::splat(0.5),
+                air: Vec2::ONE,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn run(grid: &impl CollisionGrid, pos: Vec2, speed: Vec2, on_ground: bool) -> StepResult {
+        step(
+            grid,
+            Position::from(pos),
+            with_speed(speed),
+            &collider(),
+            Some(&gravity()),
+            on_ground,
+            &WaterPhysics::default(),
+        )
+    }
+
+    fn assert_close(actual: Vec2, expected: Vec2) {
+        assert!(
+            (actual - expected).abs().max_element() < 1e-4,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    /// A floor below `y = 0` and walls left of `x = 4` and from `x = 5` on
+    fn corridor(cell: IVec2) -> CollisionKind {
+        if cell.y < 0 || cell.x < 4 || cell.x >= 5 {
+            CollisionKind::Solid
+        } else {
+            CollisionKind::Passable
+        }
+    }
+
+    #[test]
+    fn stops_at_right_wall() {
+        let result = run(&corridor, Vec2::new(4.5, 0.), Vec2::new(0.5, 0.), true);
+
+        assert_close(result.position.total_position(), Vec2::new(4.8, 0.));
+        assert_close(result.speed.speed, Vec2::ZERO);
+        assert!(result.contacts.wall_right);
+        assert!(!result.contacts.wall_left);
+        assert!(result.contacts.ground);
+        assert_close(result.contacts.impact, Vec2::new(0.5, 0.));
+    }
+
+    #[test]
+    fn stops_at_left_wall() {
+        let result = run(&corridor, Vec2::new(4.5, 0.), Vec2::new(-0.5, 0.), true);
+
+        assert_close(result.position.total_position(), Vec2::new(4.2, 0.));
+        assert_close(result.speed.speed, Vec2::ZERO);
+        assert!(result.contacts.wall_left);
+        assert!(!result.contacts.wall_right);
+        assert!(result.contacts.ground);
+        assert_close(result.contacts.impact, Vec2::new(-0.5, 0.));
+    }
+
+    #[test]
+    fn stays_against_wall_when_pushing_into_it() {
+        let result = run(&corridor, Vec2::new(4.8, 0.), Vec2::new(0.1, 0.), true);
+
+        assert_close(result.position.total_position(), Vec2::new(4.8, 0.));
+        assert_close(result.speed.speed, Vec2::ZERO);
+        assert!(result.contacts.wall_right);
+    }
+
+    #[test]
+    fn stops_at_tall_side_of_slopes() {
+        // A slope rising to the right at `x = 3`, and one falling to the right at `x = 6`
+        let valley = |cell: IVec2| match (cell.x, cell.y) {
+            (_, y) if y < 0 => CollisionKind::Solid,
+            (3, 0) => CollisionKind::Slope(Slope::Up),
+            (6, 0) => CollisionKind::Slope(Slope::Down),
+            _ => CollisionKind::Passable,
+        };
+
+        let from_right = run(&valley, Vec2::new(4.5, 0.), Vec2::new(-0.5, 0.), true);
+
+        assert_close(from_right.position.total_position(), Vec2::new(4.2, 0.));
+        assert!(from_right.contacts.wall_left);
+
+        let from_left = run(&valley, Vec2::new(5.5, 0.), Vec2::new(0.5, 0.), true);
+
+        assert_close(from_left.position.total_position(), Vec2::new(5.8, 0.));
+        assert!(from_left.contacts.wall_right);
+    }
+
+    #[test]
+    fn lands_on_floor() {
+        let floor = |cell: IVec2| {
+            if cell.y < 0 {
+                CollisionKind::Solid
+            } else {
+                CollisionKind::Passable
+            }
+        };
+
+        let result = run(&floor, Vec2::new(2.5, 0.3), Vec2::new(0., -0.5), false);
+
+        assert_close(result.position.total_position(), Vec2::new(2.5, 0.));
+        assert_close(result.speed.speed, Vec2::ZERO);
+        assert!(result.contacts.ground);
+        assert!(!result.contacts.ceiling);
+        assert_close(result.contacts.impact, Vec2::new(0., -0.52));
+    }
+
+    #[test]
+    fn falls_no_faster_than_max_fall_speed() {
+        let open = |_: IVec2| CollisionKind::Passable;
+        let speed = Speed {
+            max_fall_speed: Some(0.25),
+            ..with_speed(Vec2::new(0., -0.25))
+        };
+
+        let result = step(
+            &open,
+            Position::from(Vec2::new(2.5, 5.)),
+            speed,
+            &collider(),
+            Some(&gravity()),
+            false,
+            &WaterPhysics::default(),
+        );
+
+        // Gravity is added first, so it cannot push the fall past the limit
+        assert_close(result.speed.speed, Vec2::new(0., -0.25));
+        assert_close(result.position.total_position(), Vec2::new(2.5, 4.75));
+    }
+
+    #[test]
+    fn bumps_into_ceiling() {
+        let room = |cell: IVec2| {
+            if cell.y < 0 || cell.y >= 3 {
+                CollisionKind::Solid
+            } else {
+                CollisionKind::Passable
+            }
+        };
+
+        let result = run(&room, Vec2::new(2.5, 1.5), Vec2::new(0.1, 0.8), false);
+
+        assert_close(result.position.total_position(), Vec2::new(2.6, 2.1));
+        assert_close(result.speed.speed, Vec2::new(0.1, 0.));
+        assert!(result.contacts.ceiling);
+        assert!(!result.contacts.ground);
+        assert_close(result.contacts.impact, Vec2::new(0., 0.78));
+    }
+
+    #[test]
+    fn bumps_into_underside_of_slopes() {
+        let room = |cell: IVec2| match (cell.x, cell.y) {
+            (_, y) if y < 0 => CollisionKind::Solid,
+            (2, 3) => CollisionKind::Slope(Slope::Up),
+            _ => CollisionKind::Passable,
+        };
+
+        let result = run(&room, Vec2::new(2.5, 1.5), Vec2::new(0.1, 0.8), false);
+
+        assert_close(result.position.total_position(), Vec2::new(2.6, 2.1));
+        assert_close(result.speed.speed, Vec2::new(0.1, 0.));
+        assert!(result.contacts.ceiling);
+    }
+
+    #[test]
+    fn spring_launch_flies_freely() {
+        let floor = |cell: IVec2| {
+            if cell.y < 0 {
+                CollisionKind::Solid
+            } else {
+                CollisionKind::Passable
+            }
+        };
+
+        // Grounded entities do not get gravity added, the launch keeps its full speed
+        let result = run(&floor, Vec2::new(2.5, 0.), Vec2::new(0., 3.), true);
+
+        assert_close(result.position.total_position(), Vec2::new(2.5, 3.));
+        assert_close(result.speed.speed, Vec2::new(0., 3.));
+        assert_eq!(result.contacts, Contacts::default());
+    }
+
+    #[test]
+    fn spring_launch_stops_at_high_ceiling() {
+        let shaft = |cell: IVec2| {
+            if cell.y < 0 || cell.y >= 10 {
+                CollisionKind::Solid
+            } else {
+                CollisionKind::Passable
+            }
+        };
+
+        let result = run(&shaft, Vec2::new(2.5, 0.), Vec2::new(0., 40.), true);
+
+        assert_close(result.position.total_position(), Vec2::new(2.5, 9.1));
+        assert_close(result.speed.speed, Vec2::ZERO);
+        assert!(result.contacts.ceiling);
+        assert_close(result.contacts.impact, Vec2::new(0., 40.));
+    }
+
+    /// Moves an entity without gravity, so only the launch speed carries it
+    fn launch(grid: &impl CollisionGrid, pos: Vec2, speed: Vec2) -> StepResult {
+        step(
+            grid,
+            Position::from(pos),
+            with_speed(speed),
+            &collider(),
+            None,
+            false,
+            &WaterPhysics::default(),
+        )
+    }
+
+    /// Where an entity launched from `start` ends up against a wall one cell thick, on either side
+    /// of `x = 10` or `y = 10`
+    fn expected_stops(speed: f32) -> [(Vec2, Vec2, Vec2, fn(&Contacts) -> bool); 4] {
+        [
+            (
+                Vec2::new(5.5, 0.),
+                Vec2::new(speed, 0.),
+                Vec2::new(9.8, 0.),
+                |contacts| contacts.wall_right,
+            ),
+            (
+                Vec2::new(14.5, 0.),
+                Vec2::new(-speed, 0.),
+                Vec2::new(11.2, 0.),
+                |contacts| contacts.wall_left,
+            ),
+            (
+                Vec2::new(2.5, 5.),
+                Vec2::new(0., speed),
+                Vec2::new(2.5, 9.1),
+                |contacts| contacts.ceiling,
+            ),
+            (
+                Vec2::new(2.5, 15.),
+                Vec2::new(0., -speed),
+                Vec2::new(2.5, 11.),
+                |contacts| contacts.ground,
+            ),
+        ]
+    }
+
+    #[test]
+    fn extreme_speeds_do_not_tunnel_through_tiles() {
+        let walls = |cell: IVec2| {
+            if cell.x == 10 || cell.y == 10 {
+                CollisionKind::Solid
+            } else {
+                CollisionKind::Passable
+            }
+        };
+
+        for speed in [50., 1000.].iter() {
+            for (start, launch_speed, stop, hit) in expected_stops(*speed).iter() {
+                let result = launch(&walls, *start, *launch_speed);
+
+                assert_close(result.position.total_position(), *stop);
+                assert_close(result.speed.speed, Vec2::ZERO);
+                assert!(hit(&result.contacts), "{:?}", result.contacts);
+            }
+        }
+    }
+
+    #[test]
+    fn extreme_speeds_do_not_tunnel_through_solid_entities() {
+        let open = |_: IVec2| CollisionKind::Passable;
+
+        for speed in [50., 1000.].iter() {
+            for (i, (start, launch_speed, stop, hit)) in expected_stops(*speed).iter().enumerate() {
+                // The same walls as boxes, one for each axis so the other does not get in the way
+                let solid = if i < 2 {
+                    Aabb {
+                        min: Vec2::new(10., -5.),
+                        max: Vec2::new(11., 5.),
+                    }
+                } else {
+                    Aabb {
+                        min: Vec2::new(0., 10.),
+                        max: Vec2::new(5., 11.),
+                    }
+                };
+                let grid = WithSolids {
+                    grid: &open,
+                    solids: &[solid],
+                };
+
+                let result = launch(&grid, *start, *launch_speed);
+
+                assert_close(result.position.total_position(), *stop);
+                assert_close(result.speed.speed, Vec2::ZERO);
+                assert!(hit(&result.contacts), "{:?}", result.contacts);
+            }
+        }
     }
 }
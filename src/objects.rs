@@ -1,14 +1,14 @@
 use std::time::Duration;
 
 use benimator::Play;
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::HashSet};
 
 use crate::{
     ldtk_map::LdtkMap,
-    map::CurrentLevel,
+    map::{entity_position, CurrentMap, LevelEvent, LevelMember},
     markers::Markers,
-    movement::{Position, Speed},
-    player::Player,
+    movement::{ApplyImpulse, Collider, MovementStages, MovementSystems, Position},
+    player::{Player, PlayerAbilities},
     CoinCount, GameAssets, MainLdtk, GRID_SIZE,
 };
 
@@ -16,11 +16,18 @@ pub struct ObjectPlugin;
 
 impl Plugin for ObjectPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(spawn_objects)
-            .add_system(interact_spring_with_player)
+        app.init_resource::<Collected>()
+            .add_system(spawn_objects)
+            .add_system_to_stage(
+                MovementStages::Movement,
+                interact_spring_with_player
+                    .label(MovementSystems::Interact)
+                    .after(MovementSystems::Control),
+            )
             .add_system(update_spring_tile)
             .add_system(interact_coin_with_player)
-            .add_system(update_coin_pickup_tile);
+            .add_system(update_coin_pickup_tile)
+            .add_system(interact_ability_pickup_with_player);
     }
 }
 
@@ -35,6 +42,7 @@ pub struct SpringBundle {
     pub transform: Transform,
     pub global_transform: GlobalTransform,
     pub position: Position,
+    pub collider: Collider,
     pub markers: Markers,
 }
 
@@ -59,28 +67,48 @@ fn update_spring_tile(
 struct Sprung;
 
 fn interact_spring_with_player(
-    mut player_query: Query<(&mut Speed, &Position), (With<Player>, Without<Spring>)>,
-    mut spring_query: Query<(&Position, &Spring, &mut Markers), With<Spring>>,
+    mut impulse_events: EventWriter<ApplyImpulse>,
+    player_query: Query<(Entity, &Position, &Collider), (With<Player>, Without<Spring>)>,
+    mut spring_query: Query<(&Position, &Collider, &Spring, &mut Markers), With<Spring>>,
 ) {
-    for (mut speed, position) in player_query.iter_mut() {
-        for (spring_pos, spring, mut spring_markers) in spring_query.iter_mut() {
-            if spring_pos.cell == position.cell && !spring_markers.contains::<Sprung>() {
-                speed.speed.y += spring.force;
+    for (player, position, collider) in player_query.iter() {
+        let player_box = collider.aabb(position);
+
+        for (spring_pos, spring_collider, spring, mut spring_markers) in spring_query.iter_mut() {
+            if spring_collider.aabb(spring_pos).intersects(&player_box)
+                && !spring_markers.contains::<Sprung>()
+            {
+                impulse_events.send(ApplyImpulse::add(player, Vec2::new(0., spring.force)));
                 spring_markers.add_marker_for::<Sprung>(Duration::from_millis(1000));
             }
         }
     }
 }
 
+/// Where an LDtk entity was placed, which stays the same when its level is streamed in again
+///
+/// The map's LDtk version has no entity `iid`s yet, the level and cell stand in for them.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct PlacedEntity {
+    pub level: String,
+    pub cell: [i32; 2],
+}
+
+/// The placed entities the player already collected, they are not spawned again
+#[derive(Debug, Default)]
+pub struct Collected(pub HashSet<PlacedEntity>);
+
 #[derive(Debug, Default)]
 pub struct Coin;
 
 #[derive(Default, Bundle)]
 pub struct CoinBundle {
     pub spring: Coin,
+    pub placed: PlacedEntity,
     pub transform: Transform,
     pub global_transform: GlobalTransform,
     pub position: Position,
+    pub collider: Collider,
     pub markers: Markers,
 }
 
@@ -105,13 +133,17 @@ fn interact_coin_with_player(
     mut commands: Commands,
     game_assets: Res<GameAssets>,
     mut coin_count: ResMut<CoinCount>,
-    mut player_query: Query<&Position, With<Player>>,
-    mut coin_query: Query<(Entity, &Position), With<Coin>>,
+    mut collected: ResMut<Collected>,
+    mut player_query: Query<(&Position, &Collider), With<Player>>,
+    mut coin_query: Query<(Entity, &Position, &Collider, &PlacedEntity), With<Coin>>,
 ) {
-    for player_position in player_query.iter_mut() {
-        for (entity, coin_pos) in coin_query.iter_mut() {
-            if coin_pos.cell == player_position.cell {
+    for (player_position, player_collider) in player_query.iter_mut() {
+        let player_box = player_collider.aabb(player_position);
+
+        for (entity, coin_pos, coin_collider, placed) in coin_query.iter_mut() {
+            if coin_collider.aabb(coin_pos).intersects(&player_box) {
                 commands.entity(entity).despawn_recursive();
+                collected.0.insert(placed.clone());
                 commands
                     .spawn_bundle(CoinPickupBundle {
                         position: *coin_pos,
@@ -143,52 +175,78 @@ fn interact_coin_with_player(
     }
 }
 
-fn spawn_objects(
+/// An ability the player can unlock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ability {
+    DoubleJump,
+    Dash,
+}
+
+/// Unlocks its ability for the player touching it
+#[derive(Debug)]
+pub struct AbilityPickup(pub Ability);
+
+fn interact_ability_pickup_with_player(
     mut commands: Commands,
-    mut ldtk_map_asset_events: EventReader<AssetEvent<MainLdtk>>,
-    ldtk_assets: Res<Assets<MainLdtk>>,
-    game_assets: Res<GameAssets>,
-    old_objects_query: Query<Entity, Or<(With<Spring>, With<Coin>)>>,
-    levels_query: Query<&CurrentLevel>,
+    mut collected: ResMut<Collected>,
+    mut player_query: Query<(&Position, &Collider, &mut PlayerAbilities), With<Player>>,
+    pickup_query: Query<(Entity, &Position, &Collider, &AbilityPickup, &PlacedEntity)>,
 ) {
-    let last_event = ldtk_map_asset_events.iter().last();
+    for (player_position, player_collider, mut abilities) in player_query.iter_mut() {
+        let player_box = player_collider.aabb(player_position);
+
+        for (entity, pickup_pos, pickup_collider, pickup, placed) in pickup_query.iter() {
+            if pickup_collider.aabb(pickup_pos).intersects(&player_box) {
+                info!("Unlocked {:?}", pickup.0);
 
-    let event = if let Some(event) = last_event {
-        event
-    } else {
-        return;
-    };
+                match pickup.0 {
+                    Ability::DoubleJump => abilities.double_jump = true,
+                    Ability::Dash => abilities.dash = true,
+                }
 
-    for entity in old_objects_query.iter() {
-        commands.entity(entity).despawn_recursive();
+                commands.entity(entity).despawn_recursive();
+                collected.0.insert(placed.clone());
+            }
+        }
     }
+}
 
-    match event {
-        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
-            let ldtk = if let Some(LdtkMap { ldtk }) = ldtk_assets.get(handle) {
-                ldtk
-            } else {
-                return;
-            };
+fn spawn_objects(
+    mut commands: Commands,
+    mut level_events: EventReader<LevelEvent>,
+    ldtk_assets: Res<Assets<MainLdtk>>,
+    game_assets: Res<GameAssets>,
+    current_map: Res<CurrentMap>,
+    collected: Res<Collected>,
+) {
+    for event in level_events.iter() {
+        let level_identifier = match event {
+            LevelEvent::Loaded(level_identifier) => level_identifier,
+            LevelEvent::Unloaded(_) => continue,
+        };
 
-            for current_level in levels_query.iter() {
+        match ldtk_assets.get(&current_map.0) {
+            Some(LdtkMap { ldtk }) => {
                 let level = if let Some(level) = ldtk
                     .levels
                     .iter()
-                    .find(|level| level.identifier == current_level.0)
+                    .find(|level| &level.identifier == level_identifier)
                 {
                     level
                 } else {
-                    error!("Could not find level: {}", current_level.0);
+                    error!("Could not find level: {}", level_identifier);
                     continue;
                 };
 
                 match &level.layers.entities.special {
                     bevy_spicy_ldtk::SpecialValues::Entities(entities) => {
                         for spring in &entities.all_spring {
-                            let pos = spring.position_cell.as_f32()
-                                + level.world_position_px.as_f32() / GRID_SIZE as f32
-                                + spring.pivot * spring.dimensions_px.as_f32() / GRID_SIZE as f32;
+                            let pos = entity_position(
+                                level.world_position_px,
+                                spring.position_cell,
+                                spring.pivot,
+                                spring.dimensions_px,
+                            );
 
                             info!(
                                 "Spawning spring at: {} from {}",
@@ -202,9 +260,11 @@ fn spawn_objects(
                                         force: spring.fields.force as f32,
                                     },
                                     position: Position::from(pos),
+                                    collider: Collider::from_dimensions_px(spring.dimensions_px),
                                     transform: Transform::from_xyz(0., 0., 1.5),
                                     ..Default::default()
                                 })
+                                .insert(LevelMember(level_identifier.clone()))
                                 .with_children(|parent| {
                                     parent.spawn_bundle(SpriteSheetBundle {
                                         texture_atlas: game_assets.texture_atlas_handle.clone(),
@@ -223,9 +283,21 @@ fn spawn_objects(
                         }
 
                         for coin in &entities.all_coin {
-                            let pos = coin.position_cell.as_f32()
-                                + level.world_position_px.as_f32() / GRID_SIZE as f32
-                                + coin.pivot * coin.dimensions_px.as_f32() / GRID_SIZE as f32;
+                            let placed = PlacedEntity {
+                                level: level_identifier.clone(),
+                                cell: coin.position_cell.to_array(),
+                            };
+
+                            if collected.0.contains(&placed) {
+                                continue;
+                            }
+
+                            let pos = entity_position(
+                                level.world_position_px,
+                                coin.position_cell,
+                                coin.pivot,
+                                coin.dimensions_px,
+                            );
 
                             info!(
                                 "Spawning spring at: {} from {}",
@@ -236,10 +308,13 @@ fn spawn_objects(
                             commands
                                 .spawn_bundle(CoinBundle {
                                     spring: Coin,
+                                    placed,
                                     position: Position::from(pos),
+                                    collider: Collider::from_dimensions_px(coin.dimensions_px),
                                     transform: Transform::from_xyz(0., 0., 1.5),
                                     ..Default::default()
                                 })
+                                .insert(LevelMember(level_identifier.clone()))
                                 .with_children(|parent| {
                                     parent
                                         .spawn_bundle(SpriteSheetBundle {
@@ -259,11 +334,74 @@ fn spawn_objects(
                                         .insert(Play);
                                 });
                         }
+
+                        let pickups = entities
+                            .all_double_jump_pickup
+                            .iter()
+                            .map(|pickup| {
+                                (
+                                    Ability::DoubleJump,
+                                    pickup.position_cell,
+                                    pickup.pivot,
+                                    pickup.dimensions_px,
+                                    &game_assets.double_jump_pickup_material,
+                                )
+                            })
+                            .chain(entities.all_dash_pickup.iter().map(|pickup| {
+                                (
+                                    Ability::Dash,
+                                    pickup.position_cell,
+                                    pickup.pivot,
+                                    pickup.dimensions_px,
+                                    &game_assets.dash_pickup_material,
+                                )
+                            }));
+
+                        for (ability, position_cell, pivot, dimensions_px, material) in pickups {
+                            let placed = PlacedEntity {
+                                level: level_identifier.clone(),
+                                cell: position_cell.to_array(),
+                            };
+
+                            if collected.0.contains(&placed) {
+                                continue;
+                            }
+
+                            let pos = entity_position(
+                                level.world_position_px,
+                                position_cell,
+                                pivot,
+                                dimensions_px,
+                            );
+
+                            info!("Spawning {:?} pickup at: {}", ability, pos);
+
+                            let size = dimensions_px.as_f32();
+
+                            commands
+                                .spawn_bundle((
+                                    AbilityPickup(ability),
+                                    placed,
+                                    Position::from(pos),
+                                    Collider::from_dimensions_px(dimensions_px),
+                                    Transform::from_xyz(0., 0., 1.5),
+                                    GlobalTransform::default(),
+                                ))
+                                .insert(LevelMember(level_identifier.clone()))
+                                .with_children(|parent| {
+                                    parent.spawn_bundle(SpriteBundle {
+                                        material: material.clone(),
+                                        sprite: Sprite::new(size / 2.),
+                                        transform: Transform::from_xyz(0., size.y / 2., 0.),
+                                        ..Default::default()
+                                    });
+                                });
+                        }
                     }
                     _ => (),
                 }
             }
+            None => (),
         }
-        _ => (),
     }
 }
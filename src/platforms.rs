@@ -0,0 +1,264 @@
+use bevy::prelude::*;
+
+use crate::{
+    collision::{CollisionWorld, WithSolids},
+    ldtk_map::LdtkMap,
+    map::{entity_position, CurrentMap, LevelEvent, LevelMember},
+    movement::{
+        slide, Aabb, Collider, ContactEvent, MovementStages, MovementSystems, Position,
+        PreviousPosition, Solid, Speed, TICKS_PER_SECOND,
+    },
+    GameAssets, MainLdtk,
+};
+
+pub struct PlatformPlugin;
+
+impl Plugin for PlatformPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(spawn_platforms).add_system_to_stage(
+            MovementStages::Movement,
+            move_platforms
+                .label(MovementSystems::MoveSolids)
+                .after(MovementSystems::ReadInput),
+        );
+    }
+}
+
+/// A solid platform that travels along its path, then starts over at the first point
+#[derive(Debug, Default)]
+pub struct MovingPlatform {
+    /// The positions the platform visits, in cells
+    pub path: Vec<Vec2>,
+    /// Cells per movement tick
+    pub speed: f32,
+    /// The index of the path point the platform is heading to
+    pub next: usize,
+}
+
+#[derive(Default, Bundle)]
+pub struct MovingPlatformBundle {
+    pub platform: MovingPlatform,
+    pub solid: Solid,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+    pub position: Position,
+    pub previous_position: PreviousPosition,
+    pub collider: Collider,
+}
+
+/// How far `entity` has to be pushed along the platform's movement to get out of `platform`
+///
+/// Only the axes the platform moves along are considered, the shorter push wins.
+fn push_out_of(entity: &Aabb, platform: &Aabb, delta: Vec2) -> Vec2 {
+    let x = if delta.x > 0. {
+        platform.max.x - entity.min.x
+    } else if delta.x < 0. {
+        platform.min.x - entity.max.x
+    } else {
+        f32::INFINITY
+    };
+    let y = if delta.y > 0. {
+        platform.max.y - entity.min.y
+    } else if delta.y < 0. {
+        platform.min.y - entity.max.y
+    } else {
+        f32::INFINITY
+    };
+
+    if x.abs() <= y.abs() {
+        Vec2::new(x, 0.)
+    } else {
+        Vec2::new(0., y)
+    }
+}
+
+/// Moves platforms along their path, together with everything standing on top of them
+///
+/// Anything with a collider resting on top rides along, like the player, crates or coins. Moving
+/// entities in the way are pushed out of it, placed objects like coins are left where they are.
+/// Both are moved through the same collision as any other movement. If an entity cannot be pushed
+/// far enough, it is crushed and the platform waits for it instead of moving into it.
+fn move_platforms(
+    mut contact_events: EventWriter<ContactEvent>,
+    collision_world: Res<CollisionWorld>,
+    mut platform_query: Query<(Entity, &mut MovingPlatform, &mut Position, &Collider), With<Solid>>,
+    solids_query: Query<(Entity, &Position, &Collider), (With<Solid>, Without<MovingPlatform>)>,
+    mut rider_query: Query<(Entity, &mut Position, &Collider, Option<&Speed>), Without<Solid>>,
+) {
+    // Where every solid is right now, kept up to date as the platforms move
+    let mut solids: Vec<_> = solids_query
+        .iter()
+        .map(|(entity, position, collider)| (entity, collider.aabb(position)))
+        .collect();
+    solids.extend(
+        platform_query
+            .iter_mut()
+            .map(|(entity, _, position, collider)| (entity, collider.aabb(&position))),
+    );
+
+    for (platform_entity, mut platform, mut position, collider) in platform_query.iter_mut() {
+        let target = if let Some(target) = platform.path.get(platform.next) {
+            *target
+        } else {
+            continue;
+        };
+
+        let current = position.total_position();
+        let to_target = target - current;
+
+        let reached = to_target.length() <= platform.speed;
+        let new_position = if reached {
+            target
+        } else {
+            current + to_target.normalize() * platform.speed
+        };
+
+        let platform_box = collider.aabb(&position);
+        let moved_box = collider.aabb(&Position::from(new_position));
+        let delta = new_position - current;
+
+        // Riders and pushed entities collide with everything but the platform itself
+        let others: Vec<_> = solids
+            .iter()
+            .filter(|(entity, _)| *entity != platform_entity)
+            .map(|(_, aabb)| *aabb)
+            .collect();
+        let grid = WithSolids {
+            grid: &*collision_world,
+            solids: &others,
+        };
+
+        let mut moves = vec![];
+        let mut crushed = vec![];
+
+        for (entity, rider_position, rider_collider, speed) in rider_query.iter_mut() {
+            let rider_box = rider_collider.aabb(&rider_position);
+
+            let is_riding = (rider_box.min.y - platform_box.max.y).abs() <= 0.001
+                && rider_box.min.x < platform_box.max.x
+                && platform_box.min.x < rider_box.max.x;
+
+            if is_riding {
+                let (moved, _) = slide(&grid, *rider_position, rider_collider, delta);
+                moves.push((entity, moved));
+            } else if speed.is_some() && rider_box.intersects(&moved_box) {
+                let push = push_out_of(&rider_box, &moved_box, delta);
+                let (pushed, blocked) = slide(&grid, *rider_position, rider_collider, push);
+
+                if blocked {
+                    crushed.push(entity);
+                } else {
+                    moves.push((entity, pushed));
+                }
+            }
+        }
+
+        if !crushed.is_empty() {
+            for entity in crushed {
+                contact_events.send(ContactEvent::Crushed { entity });
+            }
+            continue;
+        }
+
+        for (entity, moved) in moves {
+            if let Ok((_, mut rider_position, _, _)) = rider_query.get_mut(entity) {
+                *rider_position = moved;
+            }
+        }
+
+        if reached {
+            platform.next = (platform.next + 1) % platform.path.len();
+        }
+
+        *position = Position::from(new_position);
+
+        if let Some((_, aabb)) = solids
+            .iter_mut()
+            .find(|(entity, _)| *entity == platform_entity)
+        {
+            *aabb = moved_box;
+        }
+    }
+}
+
+fn spawn_platforms(
+    mut commands: Commands,
+    mut level_events: EventReader<LevelEvent>,
+    ldtk_assets: Res<Assets<MainLdtk>>,
+    current_map: Res<CurrentMap>,
+    game_assets: Res<GameAssets>,
+) {
+    for event in level_events.iter() {
+        let level_identifier = match event {
+            LevelEvent::Loaded(level_identifier) => level_identifier,
+            LevelEvent::Unloaded(_) => continue,
+        };
+
+        let ldtk = if let Some(LdtkMap { ldtk }) = ldtk_assets.get(&current_map.0) {
+            ldtk
+        } else {
+            continue;
+        };
+
+        let level = if let Some(level) = ldtk
+            .levels
+            .iter()
+            .find(|level| &level.identifier == level_identifier)
+        {
+            level
+        } else {
+            error!("Could not find level: {}", level_identifier);
+            continue;
+        };
+
+        let entities = match &level.layers.entities.special {
+            bevy_spicy_ldtk::SpecialValues::Entities(entities) => entities,
+            _ => continue,
+        };
+
+        for platform in &entities.all_moving_platform {
+            // Path points are placed like the platform itself
+            let position_of = |cell: IVec2| {
+                entity_position(
+                    level.world_position_px,
+                    cell,
+                    platform.pivot,
+                    platform.dimensions_px,
+                )
+            };
+
+            let pos = position_of(platform.position_cell);
+
+            let path = std::iter::once(pos)
+                .chain(platform.fields.path.iter().map(|point| position_of(*point)))
+                .collect();
+
+            info!("Spawning moving platform at: {}", pos);
+
+            let size = platform.dimensions_px.as_f32();
+
+            commands
+                .spawn_bundle(MovingPlatformBundle {
+                    platform: MovingPlatform {
+                        path,
+                        speed: (platform.fields.speed as f64 / TICKS_PER_SECOND) as f32,
+                        next: 1,
+                    },
+                    position: Position::from(pos),
+                    previous_position: PreviousPosition(Position::from(pos)),
+                    collider: Collider::from_dimensions_px(platform.dimensions_px),
+                    transform: Transform::from_xyz(0., 0., 1.5),
+                    ..Default::default()
+                })
+                .insert(LevelMember(level_identifier.clone()))
+                .with_children(|parent| {
+                    parent.spawn_bundle(SpriteBundle {
+                        material: game_assets.platform_material.clone(),
+                        sprite: Sprite::new(size),
+                        transform: Transform::from_xyz(0., size.y / 2., 0.),
+                        ..Default::default()
+                    });
+                });
+        }
+    }
+}
@@ -1,11 +1,21 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
+use serde::Deserialize;
 
 use crate::{
+    actions::{Action, Actions},
     camera::CameraFollow,
+    collision::{CollisionGrid, CollisionKind, CollisionWorld},
     ldtk_map::LdtkMap,
-    map::CurrentLevel,
-    markers::Markers,
-    movement::{Gravity, MovementStages, OnGround, Position, Speed},
+    map::entity_position,
+    markers::{after_this_tick, Markers},
+    movement::{
+        ApplyImpulse, Climbing, Collider, CoyoteTime, Deterministic, Gravity, InWater, InputLocked,
+        MovementStages, MovementSystems, OnClimbable, OnGround, OnWall, Position, PreviousPosition,
+        Speed, WallSide,
+    },
+    tuning::PhysicsTuning,
     GameAssets, MainLdtk, GRID_SIZE,
 };
 
@@ -13,9 +23,27 @@ pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(spawn_player)
+        app.init_resource::<IntentRecording>()
+            .add_system(spawn_player)
             .add_system(check_player_intent)
-            .add_system_to_stage(MovementStages::Movement, move_player);
+            .add_system_to_stage(
+                MovementStages::Movement,
+                record_player_intent
+                    .label(MovementSystems::ReadInput)
+                    .after(MovementSystems::StorePreviousPosition),
+            )
+            .add_system_to_stage(
+                MovementStages::Movement,
+                move_player
+                    .label(MovementSystems::Control)
+                    .after(MovementSystems::ApplyForces),
+            )
+            .add_system_to_stage(
+                MovementStages::Movement,
+                knock_player_off_hazards
+                    .label(MovementSystems::Interact)
+                    .after(MovementSystems::Control),
+            );
     }
 }
 
@@ -30,18 +58,70 @@ pub struct PlayerBundle {
     pub global_transform: GlobalTransform,
     pub speed: Speed,
     pub position: Position,
+    pub previous_position: PreviousPosition,
+    pub collider: Collider,
     pub gravity: Gravity,
     pub intent: PlayerIntent,
+    pub abilities: PlayerAbilities,
+    pub jump: PlayerJump,
     pub markers: Markers,
 }
 
+/// How the player's jumps and falls feel
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct PlayerJump {
+    /// Multiplied with the upward speed when jump is released before the apex
+    pub release_cut: f32,
+    /// Vertical speed below which a held jump counts as being at its apex
+    pub apex_speed: f32,
+    /// Multiplied with gravity while hanging at the apex
+    pub apex_gravity_scale: f32,
+    /// The fastest the player falls
+    pub terminal_fall_speed: f32,
+}
+
+impl Default for PlayerJump {
+    fn default() -> Self {
+        Self {
+            release_cut: 0.5,
+            apex_speed: 0.05,
+            apex_gravity_scale: 0.5,
+            terminal_fall_speed: 0.25,
+        }
+    }
+}
+
+/// What the player is able to do besides running and jumping
+#[derive(Debug, Clone)]
+pub struct PlayerAbilities {
+    /// Fall slowly while pushing against a wall
+    pub wall_slide: bool,
+    /// Jump off a wall while in the air
+    pub wall_jump: bool,
+    /// Jump once more while in the air, unlocked by a pickup
+    pub double_jump: bool,
+    /// Dash towards the held direction, unlocked by a pickup
+    pub dash: bool,
+}
+
+impl Default for PlayerAbilities {
+    fn default() -> Self {
+        Self {
+            wall_slide: true,
+            wall_jump: true,
+            double_jump: false,
+            dash: false,
+        }
+    }
+}
+
 fn spawn_player(
     mut commands: Commands,
     mut ldtk_map_asset_events: EventReader<AssetEvent<MainLdtk>>,
     ldtk_assets: Res<Assets<MainLdtk>>,
     game_assets: Res<GameAssets>,
-    player_query: Query<Entity, With<Player>>,
-    levels_query: Query<&CurrentLevel>,
+    tuning: Res<PhysicsTuning>,
+    player_query: Query<(Entity, &PlayerAbilities), With<Player>>,
 ) {
     let last_event = ldtk_map_asset_events.iter().last();
 
@@ -51,7 +131,11 @@ fn spawn_player(
         return;
     };
 
-    for entity in player_query.iter() {
+    // Unlocks carry over to the player spawned for the reloaded map
+    let mut abilities = PlayerAbilities::default();
+
+    for (entity, old_abilities) in player_query.iter() {
+        abilities = old_abilities.clone();
         commands.entity(entity).despawn_recursive();
     }
 
@@ -63,28 +147,22 @@ fn spawn_player(
                 return;
             };
 
-            for current_level in levels_query.iter() {
-                let level = if let Some(level) = ldtk
-                    .levels
-                    .iter()
-                    .find(|level| level.identifier == current_level.0)
-                {
-                    level
-                } else {
-                    error!("Could not find level: {}", current_level.0);
-                    continue;
-                };
+            // The player start can be in any level of the world, the map follows the player
+            for level in ldtk.levels.iter() {
                 match &level.layers.entities.special {
                     bevy_spicy_ldtk::SpecialValues::Entities(entities) => {
                         let player = if let Some(player) = entities.all_player.first() {
                             player
                         } else {
-                            return;
+                            continue;
                         };
 
-                        let pos = player.position_cell.as_f32()
-                            + level.world_position_px.as_f32() / GRID_SIZE as f32
-                            + player.pivot * player.dimensions_px.as_f32() / GRID_SIZE as f32;
+                        let pos = entity_position(
+                            level.world_position_px,
+                            player.position_cell,
+                            player.pivot,
+                            player.dimensions_px,
+                        );
 
                         info!(
                             "Spawning player at: {} ({:?}) ({} + {})",
@@ -97,10 +175,18 @@ fn spawn_player(
                         commands
                             .spawn_bundle(PlayerBundle {
                                 position: Position::from(pos),
+                                previous_position: PreviousPosition(Position::from(pos)),
                                 transform: Transform::from_translation(
-                                    (pos * crate::GRID_SIZE as f32).round().extend(1.5),
+                                    (pos * GRID_SIZE as f32).round().extend(1.5),
                                 ),
-                                gravity: Gravity(Vec2::new(0., -0.02)),
+                                collider: Collider::from_dimensions_px(player.dimensions_px),
+                                speed: Speed {
+                                    friction: tuning.friction,
+                                    ..Default::default()
+                                },
+                                gravity: tuning.gravity(),
+                                jump: tuning.jump,
+                                abilities: abilities.clone(),
                                 ..Default::default()
                             })
                             .with_children(|parent| {
@@ -139,6 +225,8 @@ fn spawn_player(
                                 //     ..Default::default()
                                 // });
                             });
+
+                        return;
                     }
                     _ => (),
                 }
@@ -148,56 +236,660 @@ fn spawn_player(
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum PlayerDirection {
     Left,
     Right,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerticalDirection {
+    Up,
+    Down,
+}
+
+/// What the player wants to do, held buttons are kept between ticks until the input changes
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct PlayerIntent {
     pub direction: Option<PlayerDirection>,
+    /// How far the player pushes into `direction`, from `0.` to `1.`
+    pub strength: f32,
+    pub vertical: Option<VerticalDirection>,
+    /// Jump was pressed, only seen by a single tick
     pub jump: bool,
+    pub jump_held: bool,
+    /// Dash was pressed, only seen by a single tick
+    pub dash: bool,
 }
 
+/// Set for a moment after jump was pressed, the player jumps once it is possible until it is gone
+#[derive(Debug)]
+pub struct JumpBuffered;
+
+/// Set while the player rises from a jump, which can still be cut short or hang at its apex
+#[derive(Debug)]
+pub struct Jumping;
+
+/// Set once the player used up the double jump, until they are back on the ground
+#[derive(Debug)]
+pub struct DoubleJumped;
+
+/// Set after a dash, the next one is possible once it is gone
+#[derive(Debug)]
+pub struct DashCooldown;
+
 impl PlayerIntent {
+    /// Clears the presses once a tick used them, so a frame running several ticks uses them once
     fn reset(&mut self) {
-        *self = Default::default();
+        self.jump = false;
+        self.dash = false;
     }
 }
 
-fn check_player_intent(
-    keyboard: Res<Input<KeyCode>>,
+/// The player's intent at every movement tick, recorded in deterministic mode
+///
+/// With `replay` set, the recorded intents are played back instead of the live input, which
+/// reproduces the recorded run exactly.
+#[derive(Debug, Default)]
+pub struct IntentRecording {
+    pub ticks: Vec<PlayerIntent>,
+    pub replay: bool,
+    /// How many ticks were played back so far
+    pub replayed: usize,
+}
+
+/// Records the intent each movement tick starts with, or replaces it with the recorded one
+fn record_player_intent(
+    deterministic: Res<Deterministic>,
+    mut recording: ResMut<IntentRecording>,
     mut player_query: Query<&mut PlayerIntent, With<Player>>,
 ) {
+    if !deterministic.0 {
+        return;
+    }
+
     for mut player_intent in player_query.iter_mut() {
-        if keyboard.pressed(KeyCode::A) {
-            player_intent.direction = Some(PlayerDirection::Left);
-        }
-        if keyboard.pressed(KeyCode::D) {
-            player_intent.direction = Some(PlayerDirection::Right);
+        if recording.replay {
+            *player_intent = recording
+                .ticks
+                .get(recording.replayed)
+                .cloned()
+                .unwrap_or_default();
+            recording.replayed += 1;
+        } else {
+            recording.ticks.push(player_intent.clone());
         }
+    }
+}
+
+fn check_player_intent(
+    actions: Res<Actions>,
+    mut player_query: Query<&mut PlayerIntent, With<Player>>,
+) {
+    for mut player_intent in player_query.iter_mut() {
+        let horizontal = actions.horizontal();
+
+        player_intent.direction = if horizontal < 0. {
+            Some(PlayerDirection::Left)
+        } else if horizontal > 0. {
+            Some(PlayerDirection::Right)
+        } else {
+            None
+        };
+        player_intent.strength = horizontal.abs();
+        player_intent.vertical = if actions.pressed(Action::MoveDown) {
+            Some(VerticalDirection::Down)
+        } else if actions.pressed(Action::MoveUp) {
+            Some(VerticalDirection::Up)
+        } else {
+            None
+        };
+
+        player_intent.jump_held = actions.pressed(Action::Jump);
 
-        if keyboard.just_pressed(KeyCode::Space) {
+        if actions.just_pressed(Action::Jump) {
             player_intent.jump = true;
         }
+
+        if actions.just_pressed(Action::Dash) {
+            player_intent.dash = true;
+        }
     }
 }
 
-fn move_player(mut player_query: Query<(&mut Speed, &mut PlayerIntent, &Markers), With<Player>>) {
-    for (mut speed, mut player_intent, markers) in player_query.iter_mut() {
+fn move_player(
+    tuning: Res<PhysicsTuning>,
+    mut impulse_events: EventWriter<ApplyImpulse>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Speed,
+            &mut PlayerIntent,
+            &PlayerAbilities,
+            &Gravity,
+            &PlayerJump,
+            &mut Markers,
+        ),
+        With<Player>,
+    >,
+) {
+    for (player, mut speed, mut player_intent, abilities, gravity, jump, mut markers) in
+        player_query.iter_mut()
+    {
+        if markers.contains::<InputLocked>() {
+            speed.max_fall_speed = Some(jump.terminal_fall_speed);
+            player_intent.reset();
+            continue;
+        }
+
+        // Jumps and falls are relative to the ground, which is above while upside down
+        let up = gravity.up();
+        let on_ground = markers.contains::<OnGround>();
+        let on_wall = markers.get::<OnWall>().filter(|_| !on_ground);
+
         match &player_intent.direction {
             Some(direction) => match direction {
-                PlayerDirection::Left => speed.speed.x -= 0.06,
-                PlayerDirection::Right => speed.speed.x += 0.06,
+                PlayerDirection::Left => {
+                    speed.speed.x -= tuning.acceleration * player_intent.strength
+                }
+                PlayerDirection::Right => {
+                    speed.speed.x += tuning.acceleration * player_intent.strength
+                }
             },
             None => (),
         }
 
-        if player_intent.jump && markers.contains::<OnGround>() {
-            speed.speed.y += 0.7;
+        // Grabbing needs a direction and is not possible while still rising from a jump, holding
+        // on only needs the climbable tile
+        let grabbing = player_intent.vertical.is_some() && speed.speed.y * up <= 0.;
+        let climbing =
+            markers.contains::<OnClimbable>() && (markers.contains::<Climbing>() || grabbing);
+
+        if climbing {
+            markers.add_marker_for::<Climbing>(Duration::from_millis(50));
+
+            speed.speed.y = match player_intent.vertical {
+                Some(VerticalDirection::Up) => tuning.climb_speed,
+                Some(VerticalDirection::Down) => -tuning.climb_speed,
+                None => 0.,
+            };
+        }
+
+        if markers.contains::<Jumping>() {
+            let rising = speed.speed.y * up;
+
+            if on_ground && rising <= 0. {
+                // Back on the ground, there is no apex left to hang at
+                markers.remove::<Jumping>();
+            } else if !player_intent.jump_held && rising > 0. {
+                speed.speed.y *= jump.release_cut;
+                markers.remove::<Jumping>();
+            } else if rising.abs() < jump.apex_speed {
+                // Takes back part of the gravity the movement stage is about to add
+                speed.speed.y -= gravity.0.y * (1. - jump.apex_gravity_scale);
+            } else if rising < 0. {
+                markers.remove::<Jumping>();
+            }
+        }
+
+        // The movement stage clamps the fall right after adding gravity, in whichever way it pulls.
+        // The wall contact only lasts while pushing into it, so letting go ends the slide.
+        speed.max_fall_speed = if on_wall.is_some() && abilities.wall_slide {
+            Some(tuning.wall_slide_speed)
+        } else {
+            Some(jump.terminal_fall_speed)
+        };
+
+        if on_ground {
+            markers.remove::<DoubleJumped>();
+        }
+
+        // A jump pressed shortly before it is possible happens as soon as it is. Running off a
+        // ledge keeps the jump possible for a moment through `CoyoteTime`.
+        if player_intent.jump {
+            markers.add_marker_for::<JumpBuffered>(after_this_tick(tuning.jump_buffer));
+        }
+
+        if markers.contains::<JumpBuffered>() {
+            let jumped = if climbing {
+                markers.remove::<Climbing>();
+                impulse_events.send(ApplyImpulse::add(
+                    player,
+                    Vec2::new(0., tuning.jump_impulse * up),
+                ));
+                true
+            } else if markers.contains::<CoyoteTime>() {
+                markers.remove::<CoyoteTime>();
+                impulse_events.send(ApplyImpulse::add(
+                    player,
+                    Vec2::new(0., tuning.jump_impulse * up),
+                ));
+                true
+            } else if markers.contains::<InWater>() {
+                impulse_events.send(ApplyImpulse::add(
+                    player,
+                    Vec2::new(0., tuning.water.stroke * up),
+                ));
+                true
+            } else if let Some(OnWall(side)) = on_wall.filter(|_| abilities.wall_jump) {
+                let away = match side {
+                    WallSide::Left => 1.,
+                    WallSide::Right => -1.,
+                };
+
+                impulse_events.send(ApplyImpulse::set(
+                    player,
+                    tuning.wall_jump_impulse * Vec2::new(away, up),
+                ));
+                true
+            } else if abilities.double_jump && !markers.contains::<DoubleJumped>() {
+                markers.add_marker::<DoubleJumped>();
+                // Replaces the vertical speed, so a double jump while falling is as high as any
+                impulse_events.send(ApplyImpulse::add(
+                    player,
+                    Vec2::new(0., tuning.jump_impulse * up - speed.speed.y),
+                ));
+                true
+            } else {
+                false
+            };
+
+            if jumped {
+                markers.remove::<JumpBuffered>();
+                markers.add_marker_for::<Jumping>(Duration::from_secs(2));
+            }
+        }
+
+        if player_intent.dash && abilities.dash && !markers.contains::<DashCooldown>() {
+            let horizontal = match player_intent.direction {
+                Some(PlayerDirection::Left) => -1.,
+                Some(PlayerDirection::Right) => 1.,
+                None => 0.,
+            };
+            let vertical = match player_intent.vertical {
+                Some(VerticalDirection::Up) => 1.,
+                Some(VerticalDirection::Down) => -1.,
+                None => 0.,
+            };
+            let direction = Vec2::new(horizontal, vertical);
+
+            if direction != Vec2::ZERO {
+                markers.remove::<Climbing>();
+                markers
+                    .add_marker_for::<DashCooldown>(Duration::from_secs_f32(tuning.dash_cooldown));
+                impulse_events.send(
+                    ApplyImpulse::set(player, direction.normalize() * tuning.dash_speed)
+                        .with_lockout(Duration::from_secs_f32(tuning.dash_duration)),
+                );
+            }
         }
 
         player_intent.reset();
     }
 }
+
+/// Throws the player back out of hazard tiles, away from them and upwards
+fn knock_player_off_hazards(
+    tuning: Res<PhysicsTuning>,
+    collision_world: Res<CollisionWorld>,
+    mut impulse_events: EventWriter<ApplyImpulse>,
+    player_query: Query<(Entity, &Position, &Collider, &Gravity), With<Player>>,
+) {
+    for (player, position, collider, gravity) in player_query.iter() {
+        let aabb = collider.aabb(position);
+        let hazards: Vec<_> = aabb
+            .cells()
+            .filter(|cell| collision_world.kind_at(*cell) == CollisionKind::Hazard)
+            .collect();
+
+        if hazards.is_empty() {
+            continue;
+        }
+
+        let hazard_center = hazards.iter().fold(Vec2::ZERO, |sum, cell| {
+            sum + cell.as_f32() + Vec2::splat(0.5)
+        }) / hazards.len() as f32;
+        let away = if position.total_position().x < hazard_center.x {
+            -1.
+        } else {
+            1.
+        };
+
+        impulse_events.send(ApplyImpulse::knockback(
+            player,
+            Vec2::new(away, gravity.up()),
+            tuning.hazard_knockback,
+            Duration::from_secs_f32(tuning.hazard_lockout),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::schedule::Stage;
+
+    use super::*;
+    use crate::{
+        movement::{MovementPlugin, StateHashes, TICKS_PER_SECOND},
+        objects::ObjectPlugin,
+        platforms::PlatformPlugin,
+        zones::ZonePlugin,
+    };
+
+    /// A deterministic movement simulation without rendering or assets
+    ///
+    /// Only the `Movement` stage is ever run, one tick at a time through [`tick`].
+    fn headless_app(world: CollisionWorld) -> App {
+        let mut app = App::new();
+
+        app.add_plugin(MovementPlugin {
+            fixed_timestep: false,
+        })
+        .add_plugin(PlayerPlugin)
+        .add_plugin(ObjectPlugin)
+        .add_plugin(PlatformPlugin)
+        .add_plugin(ZonePlugin)
+        .insert_resource(Deterministic(true))
+        .init_resource::<PhysicsTuning>()
+        .insert_resource(world);
+
+        app
+    }
+
+    fn tick(app: &mut App) {
+        app.schedule
+            .get_stage_mut::<SystemStage>(&MovementStages::Movement)
+            .unwrap()
+            .run(&mut app.world);
+    }
+
+    /// Solid ground below `y = 0`
+    fn flat_floor() -> CollisionWorld {
+        CollisionWorld::from_fn(IVec2::new(-50, -5), IVec2::new(100, 50), |cell| {
+            if cell.y < 0 {
+                CollisionKind::Solid
+            } else {
+                CollisionKind::Passable
+            }
+        })
+    }
+
+    fn spawn_player_at(app: &mut App, pos: Vec2) -> Entity {
+        let tuning = PhysicsTuning::default();
+
+        app.world
+            .spawn()
+            .insert_bundle(PlayerBundle {
+                position: Position::from(pos),
+                previous_position: PreviousPosition(Position::from(pos)),
+                collider: Collider::from_dimensions_px(IVec2::splat(GRID_SIZE)),
+                speed: Speed {
+                    friction: tuning.friction,
+                    ..Default::default()
+                },
+                gravity: tuning.gravity(),
+                jump: tuning.jump,
+                ..Default::default()
+            })
+            .id()
+    }
+
+    fn set_intent(app: &mut App, player: Entity, intent: PlayerIntent) {
+        *app.world.get_mut::<PlayerIntent>(player).unwrap() = intent;
+    }
+
+    fn position(app: &App, player: Entity) -> Vec2 {
+        app.world.get::<Position>(player).unwrap().total_position()
+    }
+
+    /// Running back and forth, with jumps of different heights
+    fn running_and_jumping() -> Vec<PlayerIntent> {
+        (0..180)
+            .map(|tick| PlayerIntent {
+                direction: match tick / 40 % 3 {
+                    0 => Some(PlayerDirection::Right),
+                    1 => None,
+                    _ => Some(PlayerDirection::Left),
+                },
+                strength: 1.,
+                jump: tick % 25 == 0,
+                jump_held: tick % 25 < tick % 7 + 5,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replay_reproduces_recorded_run() {
+        let start = Vec2::new(0.5, 0.);
+
+        let mut recorded = headless_app(flat_floor());
+        let player = spawn_player_at(&mut recorded, start);
+
+        // Input arrives from outside the movement stage, like it does while playing
+        for intent in running_and_jumping() {
+            set_intent(&mut recorded, player, intent);
+            tick(&mut recorded);
+        }
+
+        let recording = std::mem::take(
+            &mut recorded
+                .world
+                .get_resource_mut::<IntentRecording>()
+                .unwrap()
+                .ticks,
+        );
+
+        assert_eq!(recording, running_and_jumping());
+
+        let mut replayed = headless_app(flat_floor());
+        let replayed_player = spawn_player_at(&mut replayed, start);
+        replayed.world.insert_resource(IntentRecording {
+            ticks: recording,
+            replay: true,
+            replayed: 0,
+        });
+
+        for _ in 0..running_and_jumping().len() {
+            tick(&mut replayed);
+        }
+
+        let hashes = |app: &App| app.world.get_resource::<StateHashes>().unwrap().0.clone();
+
+        assert_eq!(hashes(&recorded).len(), running_and_jumping().len());
+        assert_eq!(hashes(&recorded), hashes(&replayed));
+        assert_eq!(
+            position(&recorded, player),
+            position(&replayed, replayed_player)
+        );
+
+        // The run went somewhere, so the hashes compared actual movement
+        assert_ne!(position(&recorded, player), start);
+    }
+
+    #[test]
+    fn hazards_knock_the_player_back() {
+        let mut app = headless_app(CollisionWorld::from_fn(
+            IVec2::new(-50, -5),
+            IVec2::new(100, 50),
+            |cell| match (cell.x, cell.y) {
+                (_, y) if y < 0 => CollisionKind::Solid,
+                (1, 0) => CollisionKind::Hazard,
+                _ => CollisionKind::Passable,
+            },
+        ));
+        let player = spawn_player_at(&mut app, Vec2::new(0.8, 0.));
+
+        tick(&mut app);
+
+        let speed = app.world.get::<Speed>(player).unwrap().speed;
+        assert!(speed.x < 0., "thrown away from the spikes: {}", speed);
+        assert!(speed.y > 0., "thrown upwards: {}", speed);
+        assert!(app
+            .world
+            .get::<Markers>(player)
+            .unwrap()
+            .contains::<InputLocked>());
+    }
+
+    fn speed(app: &App, player: Entity) -> Vec2 {
+        app.world.get::<Speed>(player).unwrap().speed
+    }
+
+    /// How many movement ticks a tuning value in seconds lasts
+    fn ticks(seconds: f32) -> usize {
+        (seconds as f64 * TICKS_PER_SECOND).round() as usize
+    }
+
+    fn jump_pressed() -> PlayerIntent {
+        PlayerIntent {
+            jump: true,
+            jump_held: true,
+            ..Default::default()
+        }
+    }
+
+    /// Walks off a ledge into a deep pit, presses jump on the `ticks_after`th tick after the last
+    /// one on the ledge and returns whether the player jumped
+    fn jump_after_walking_off_ledge(ticks_after: usize) -> bool {
+        let mut app = headless_app(CollisionWorld::from_fn(
+            IVec2::new(-50, -50),
+            IVec2::new(100, 100),
+            |cell| {
+                if (cell.y < 0 && cell.x < 3) || cell.y < -40 {
+                    CollisionKind::Solid
+                } else {
+                    CollisionKind::Passable
+                }
+            },
+        ));
+        let player = spawn_player_at(&mut app, Vec2::new(1.5, 0.));
+        let half_width = app.world.get::<Collider>(player).unwrap().size.x / 2.;
+
+        // The first tick that ends with the whole player past the ledge is the first one in the air
+        let walked_off = (0..120).any(|_| {
+            set_intent(
+                &mut app,
+                player,
+                PlayerIntent {
+                    direction: Some(PlayerDirection::Right),
+                    strength: 1.,
+                    ..Default::default()
+                },
+            );
+            tick(&mut app);
+
+            position(&app, player).x - half_width >= 3.
+        });
+        assert!(walked_off, "never left the ledge");
+
+        for _ in 2..ticks_after {
+            set_intent(&mut app, player, PlayerIntent::default());
+            tick(&mut app);
+        }
+
+        set_intent(&mut app, player, jump_pressed());
+        tick(&mut app);
+
+        speed(&app, player).y > 0.
+    }
+
+    #[test]
+    fn jumps_within_coyote_time() {
+        let coyote_ticks = ticks(PhysicsTuning::default().coyote_time);
+
+        assert!(jump_after_walking_off_ledge(2));
+        assert!(jump_after_walking_off_ledge(coyote_ticks));
+    }
+
+    #[test]
+    fn does_not_jump_after_coyote_time() {
+        let coyote_ticks = ticks(PhysicsTuning::default().coyote_time);
+
+        assert!(!jump_after_walking_off_ledge(coyote_ticks + 1));
+    }
+
+    /// Drops the player onto a floor, presses jump `ticks_before` ticks before the first tick it
+    /// starts on the ground and returns whether the player jumped off it again
+    fn jump_before_landing(ticks_before: usize) -> bool {
+        let start = Vec2::new(0.5, 10.);
+
+        // The fall is deterministic, so a first drop tells when the second one lands
+        let mut dry_run = headless_app(flat_floor());
+        let dry_player = spawn_player_at(&mut dry_run, start);
+        let landing = (0..600)
+            .find(|_| {
+                tick(&mut dry_run);
+
+                position(&dry_run, dry_player).y <= 0.
+            })
+            .expect("never landed");
+        let on_ground = landing + 1;
+        assert!(on_ground > ticks_before, "the drop is too short");
+
+        let mut app = headless_app(flat_floor());
+        let player = spawn_player_at(&mut app, start);
+        let buffer_ticks = ticks(PhysicsTuning::default().jump_buffer);
+
+        (0..=on_ground + 2 * buffer_ticks).any(|tick_index| {
+            let intent = if tick_index == on_ground - ticks_before {
+                jump_pressed()
+            } else {
+                PlayerIntent::default()
+            };
+            set_intent(&mut app, player, intent);
+            tick(&mut app);
+
+            tick_index >= on_ground && speed(&app, player).y > 0.
+        })
+    }
+
+    #[test]
+    fn jumps_when_pressed_within_jump_buffer() {
+        let buffer_ticks = ticks(PhysicsTuning::default().jump_buffer);
+
+        assert!(jump_before_landing(2));
+        assert!(jump_before_landing(buffer_ticks));
+    }
+
+    #[test]
+    fn does_not_jump_when_pressed_before_jump_buffer() {
+        let buffer_ticks = ticks(PhysicsTuning::default().jump_buffer);
+
+        assert!(!jump_before_landing(buffer_ticks + 1));
+    }
+
+    #[test]
+    fn slides_down_walls_at_wall_slide_speed() {
+        let mut app = headless_app(CollisionWorld::from_fn(
+            IVec2::new(-50, -5),
+            IVec2::new(100, 50),
+            |cell| {
+                if cell.y < 0 || cell.x >= 5 {
+                    CollisionKind::Solid
+                } else {
+                    CollisionKind::Passable
+                }
+            },
+        ));
+        let player = spawn_player_at(&mut app, Vec2::new(4.5, 20.));
+        let pushing_into_wall = PlayerIntent {
+            direction: Some(PlayerDirection::Right),
+            strength: 1.,
+            ..Default::default()
+        };
+
+        for _ in 0..30 {
+            set_intent(&mut app, player, pushing_into_wall.clone());
+            tick(&mut app);
+        }
+
+        let before = position(&app, player);
+        set_intent(&mut app, player, pushing_into_wall);
+        tick(&mut app);
+
+        let wall_slide_speed = PhysicsTuning::default().wall_slide_speed;
+        assert!((speed(&app, player).y + wall_slide_speed).abs() < 1e-4);
+        assert!((before.y - position(&app, player).y - wall_slide_speed).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,160 @@
+use bevy::asset::{AssetLoader, LoadedAsset};
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::Deserialize;
+
+use crate::{
+    movement::{Friction, Gravity, Speed, WaterPhysics},
+    player::PlayerJump,
+    GameAssets,
+};
+
+pub struct TuningPlugin;
+
+impl Plugin for TuningPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<PhysicsTuning>()
+            .add_asset_loader(TuningLoader)
+            .init_resource::<PhysicsTuning>()
+            .add_system(apply_tuning);
+    }
+}
+
+/// Everything that decides how movement feels
+///
+/// Loaded from `assets/tuning/physics.ron`, the currently active values are kept as a resource.
+#[derive(Debug, Clone, Deserialize, TypeUuid)]
+#[uuid = "5b0b2a8e-63a4-4f0c-9d0e-3c3f4b7de1a2"]
+pub struct PhysicsTuning {
+    /// How fast entities fall, added to their speed every tick
+    pub gravity: f32,
+    pub friction: Friction,
+    /// Horizontal speed the player gains every tick while moving
+    pub acceleration: f32,
+    pub jump_impulse: f32,
+    /// Seconds after leaving the ground in which the player can still jump
+    pub coyote_time: f32,
+    /// Seconds a jump pressed too early is remembered
+    pub jump_buffer: f32,
+    pub jump: PlayerJump,
+    /// The fastest the player falls while sliding down a wall
+    pub wall_slide_speed: f32,
+    /// Speed away from the wall and upwards, replacing the speed the player had
+    pub wall_jump_impulse: Vec2,
+    /// Vertical speed while climbing up or down
+    pub climb_speed: f32,
+    /// Speed of a dash, in any of the eight directions
+    pub dash_speed: f32,
+    /// Seconds the player has no control during a dash
+    pub dash_duration: f32,
+    /// Seconds after a dash until the next one
+    pub dash_cooldown: f32,
+    /// Speed the player is thrown back with when touching a hazard
+    pub hazard_knockback: f32,
+    /// Seconds the player has no control after touching a hazard
+    pub hazard_lockout: f32,
+    pub water: WaterPhysics,
+}
+
+impl Default for PhysicsTuning {
+    fn default() -> Self {
+        Self {
+            gravity: 0.035,
+            friction: Friction::default(),
+            acceleration: 0.03,
+            jump_impulse: 0.48,
+            coyote_time: 0.1,
+            jump_buffer: 0.1,
+            jump: PlayerJump::default(),
+            wall_slide_speed: 0.1,
+            wall_jump_impulse: Vec2::new(0.4, 0.42),
+            climb_speed: 0.15,
+            dash_speed: 0.6,
+            dash_duration: 0.15,
+            dash_cooldown: 0.6,
+            hazard_knockback: 0.45,
+            hazard_lockout: 0.3,
+            water: WaterPhysics::default(),
+        }
+    }
+}
+
+impl PhysicsTuning {
+    pub fn gravity(&self) -> Gravity {
+        Gravity(Vec2::new(0., -self.gravity))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TuningLoader;
+
+impl AssetLoader for TuningLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut bevy::asset::LoadContext,
+    ) -> bevy::asset::BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            info!("Loading tuning from {:?}", load_context.path());
+
+            let tuning: PhysicsTuning = ron::de::from_bytes(bytes)?;
+
+            load_context.set_default_asset(LoadedAsset::new(tuning));
+
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ron"]
+    }
+}
+
+/// Makes a (re)loaded tuning file the active one, and updates all entities that already exist
+fn apply_tuning(
+    mut tuning_asset_events: EventReader<AssetEvent<PhysicsTuning>>,
+    tuning_assets: Res<Assets<PhysicsTuning>>,
+    game_assets: Res<GameAssets>,
+    mut tuning: ResMut<PhysicsTuning>,
+    mut speed_query: Query<&mut Speed>,
+    mut gravity_query: Query<&mut Gravity>,
+    mut jump_query: Query<&mut PlayerJump>,
+) {
+    let tuning_modified = tuning_asset_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => {
+            handle == &game_assets.physics_tuning_handle
+        }
+        AssetEvent::Removed { handle: _ } => false,
+    });
+
+    if !tuning_modified {
+        return;
+    }
+
+    let new_tuning = if let Some(new_tuning) = tuning_assets.get(&game_assets.physics_tuning_handle)
+    {
+        new_tuning
+    } else {
+        return;
+    };
+
+    info!("Applying physics tuning: {:?}", new_tuning);
+
+    *tuning = new_tuning.clone();
+
+    for mut speed in speed_query.iter_mut() {
+        speed.friction = tuning.friction;
+    }
+
+    for mut gravity in gravity_query.iter_mut() {
+        *gravity = if gravity.is_inverted() {
+            tuning.gravity().flipped()
+        } else {
+            tuning.gravity()
+        };
+    }
+
+    for mut jump in jump_query.iter_mut() {
+        *jump = tuning.jump;
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::{
+    ldtk_map::LdtkMap,
+    map::{entity_position, CurrentMap, LevelEvent, LevelMember},
+    markers::Markers,
+    movement::{
+        Aabb, Collider, Gravity, MovementStages, MovementSystems, OnGround, Position, Speed,
+        TICKS_PER_SECOND,
+    },
+    MainLdtk,
+};
+
+pub struct ZonePlugin;
+
+impl Plugin for ZonePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(spawn_zones)
+            .add_system_to_stage(
+                MovementStages::Movement,
+                apply_force_zones
+                    .label(MovementSystems::ApplyForces)
+                    .after(MovementSystems::MoveSolids),
+            )
+            .add_system_to_stage(
+                MovementStages::Movement,
+                apply_gravity_flips
+                    .label(MovementSystems::ReactToPosition)
+                    .after(MovementSystems::ApplySpeed),
+            );
+    }
+}
+
+/// Accelerates every moving entity that overlaps its area, like wind or a conveyor belt
+#[derive(Debug)]
+pub struct ForceZone {
+    pub area: Aabb,
+    /// Added to the speed of overlapping entities every movement tick
+    pub acceleration: Vec2,
+}
+
+fn apply_force_zones(
+    zone_query: Query<&ForceZone>,
+    mut movement_query: Query<(&Position, &Collider, &mut Speed)>,
+) {
+    for zone in zone_query.iter() {
+        for (position, collider, mut speed) in movement_query.iter_mut() {
+            if collider.aabb(position).intersects(&zone.area) {
+                speed.speed += zone.acceleration;
+            }
+        }
+    }
+}
+
+/// Turns the gravity of entities around when they enter its area
+#[derive(Debug)]
+pub struct GravityFlip {
+    pub area: Aabb,
+}
+
+/// Set while an entity overlaps a [`GravityFlip`], so it is only flipped once when entering it
+#[derive(Debug)]
+pub struct InGravityFlip;
+
+fn apply_gravity_flips(
+    flip_query: Query<&GravityFlip>,
+    mut gravity_query: Query<(&Position, &Collider, &mut Gravity, &mut Markers)>,
+) {
+    for (position, collider, mut gravity, mut markers) in gravity_query.iter_mut() {
+        let aabb = collider.aabb(position);
+
+        if !flip_query.iter().any(|flip| aabb.intersects(&flip.area)) {
+            continue;
+        }
+
+        if !markers.contains::<InGravityFlip>() {
+            *gravity = gravity.flipped();
+            // The ground the entity stood on is now above it
+            markers.remove::<OnGround>();
+        }
+
+        markers.add_marker_for::<InGravityFlip>(Duration::from_millis(50));
+    }
+}
+
+fn spawn_zones(
+    mut commands: Commands,
+    mut level_events: EventReader<LevelEvent>,
+    ldtk_assets: Res<Assets<MainLdtk>>,
+    current_map: Res<CurrentMap>,
+) {
+    for event in level_events.iter() {
+        let level_identifier = match event {
+            LevelEvent::Loaded(level_identifier) => level_identifier,
+            LevelEvent::Unloaded(_) => continue,
+        };
+
+        let ldtk = if let Some(LdtkMap { ldtk }) = ldtk_assets.get(&current_map.0) {
+            ldtk
+        } else {
+            continue;
+        };
+
+        let level = if let Some(level) = ldtk
+            .levels
+            .iter()
+            .find(|level| &level.identifier == level_identifier)
+        {
+            level
+        } else {
+            error!("Could not find level: {}", level_identifier);
+            continue;
+        };
+
+        let entities = match &level.layers.entities.special {
+            bevy_spicy_ldtk::SpecialValues::Entities(entities) => entities,
+            _ => continue,
+        };
+
+        for zone in &entities.all_force_zone {
+            let pos = entity_position(
+                level.world_position_px,
+                zone.position_cell,
+                zone.pivot,
+                zone.dimensions_px,
+            );
+
+            // The direction field points from the zone towards a cell
+            let direction = (zone.fields.direction - zone.position_cell)
+                .as_f32()
+                .normalize_or_zero();
+
+            // The strength is given in tiles per second squared
+            let strength = (zone.fields.strength as f64 / TICKS_PER_SECOND.powi(2)) as f32;
+
+            info!(
+                "Spawning force zone at: {} pushing {}",
+                pos,
+                direction * strength
+            );
+
+            commands
+                .spawn()
+                .insert(ForceZone {
+                    area: Collider::from_dimensions_px(zone.dimensions_px)
+                        .aabb(&Position::from(pos)),
+                    acceleration: direction * strength,
+                })
+                .insert(LevelMember(level_identifier.clone()));
+        }
+
+        for flip in &entities.all_gravity_flip {
+            let pos = entity_position(
+                level.world_position_px,
+                flip.position_cell,
+                flip.pivot,
+                flip.dimensions_px,
+            );
+
+            info!("Spawning gravity flip at: {}", pos);
+
+            commands
+                .spawn()
+                .insert(GravityFlip {
+                    area: Collider::from_dimensions_px(flip.dimensions_px)
+                        .aabb(&Position::from(pos)),
+                })
+                .insert(LevelMember(level_identifier.clone()));
+        }
+    }
+}